@@ -1,11 +1,11 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
-use cosmwasm_std::{to_binary, from_binary, Addr, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult, Uint128, CosmosMsg, WasmMsg, Order, BlockInfo};
+use cosmwasm_std::{to_binary, from_binary, coin, Addr, Binary, Deps, DepsMut, Empty, Env, MessageInfo, Reply, Response, StdError, StdResult, SubMsg, SubMsgResult, Uint128, CosmosMsg, WasmMsg, StakingMsg, Order, BlockInfo};
 
-use cw2::set_contract_version;
+use cw2::{get_contract_version, set_contract_version};
 use cw20::Denom;
 use cw721::{Cw721ReceiveMsg, Cw721ExecuteMsg};
-use cw_utils::must_pay;
+use cw_utils::{may_pay, must_pay, parse_reply_instantiate_data, Expiration};
 
 use crate::util;
 use crate::error::ContractError;
@@ -18,7 +18,31 @@ use crate::msg::{
     ConfigResponse,
     NftReceiveMsg, 
     TotalEarnedResponse,
-    TotalLockedResponse
+    TotalLockedResponse,
+    LockTiersResponse,
+    NftClaimsResponse,
+    PauseFlagsResponse,
+    SponsoredResponse,
+    RewardRecipientResponse,
+    OperatorResponse,
+    VaultExecuteMsg,
+    DelegationResponse,
+    DelegationsResponse,
+    HooksResponse,
+    StakingAgentsResponse,
+    PendingTransferResponse,
+    ReceiptInstantiateMsg,
+    ReceiptExecuteMsg,
+    StakeChangeKind,
+    VotingPowerAtHeightResponse,
+    TotalPowerAtHeightResponse,
+    InfoResponse,
+    DenylistResponse,
+    NftStreakResponse,
+    StreakResponse,
+    StreakTiersResponse,
+    PendingChangeResponse,
+    PendingChangesResponse
 };
 use crate::state::{
     Config, 
@@ -26,9 +50,53 @@ use crate::state::{
     CURRENT_AIRDROP,
     START_AIRDROP,
     TOTAL_AIRDROP,
+    AIRDROP_DUST,
+    UNCLAIMED_REWARDS,
+    AIRDROP_SPONSORS,
+    REWARD_RECIPIENT,
+    OPERATORS,
+    CLAIM_MODE,
+    VAULT,
+    DELEGATIONS,
+    VALIDATOR_DELEGATIONS,
+    UNDELEGATION_PERIOD,
+    UNDELEGATION_CLAIMS,
+    HOOKS,
+    HOOK_ERRORS_FATAL,
+    VOTING_POWER,
+    TOTAL_POWER,
+    STAKING_AGENTS,
+    DENIED_TOKENS,
+    DENIED_ADDRESSES,
+    DENYLIST_MODE,
+    DenylistMode,
+    Weighting,
+    STREAK_TIERS,
+    RENEWAL_WINDOW,
+    USER_STREAKS,
+    USER_STREAK_PERIODS,
+    TIMELOCK_DELAY,
+    NEXT_CHANGE_ID,
+    PENDING_CHANGES,
+    ConfigChange,
+    RECEIPT_COLLECTION,
+    RECEIPT_HOLDERS,
+    RECEIPT_TOKENS,
+    PENDING_TRANSFERS,
+    PendingTransfer,
     TOTAL_STAKED,
     LOCKTIME_FEE,
     ACCOUNT_MAP,
+    LOCK_TIERS,
+    MAX_LOCK_DURATION,
+    UNBONDING_PERIOD,
+    NFT_CLAIMS,
+    EMERGENCY,
+    PAUSE_FLAGS,
+    PAUSER,
+    NftClaim,
+    ClaimMode,
+    PauseFlags,
     NftInfo, 
     UserInfo
 };
@@ -36,6 +104,13 @@ use crate::state::{
 const CONTRACT_NAME: &str = "crates.io:staking";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+const DEFAULT_RETURN_LIMIT: u32 = 10;
+const MAX_RETURN_LIMIT: u32 = 30;
+
+const DEFAULT_UNDELEGATION_PERIOD: u64 = 21 * 24 * 60 * 60;
+const DEFAULT_RENEWAL_WINDOW: u64 = 24 * 60 * 60;
+const DEFAULT_TIMELOCK_DELAY: u64 = 2 * 24 * 60 * 60;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
@@ -51,8 +126,13 @@ pub fn instantiate(
         fee_address: msg.fee_address.clone(),
         collection_address: msg.collection_address.clone(),
         native_token: msg.native_token.clone(),
-        duration: msg.duration.clone(),
+        duration: msg.duration,
         enabled: true,
+        max_per_user: None,
+        max_total_staked: None,
+        weighting: Weighting::Linear {  },
+        min_stake_age: 0,
+        time_weighted: false,
     };
 
     CONFIG.save(deps.storage, &config)?;
@@ -60,9 +140,37 @@ pub fn instantiate(
     START_AIRDROP.save(deps.storage, &false)?;
     LOCKTIME_FEE.save(deps.storage, &Uint128::from(1000000000000000000u128))?;
     TOTAL_AIRDROP.save(deps.storage, &Uint128::zero())?;
+    AIRDROP_DUST.save(deps.storage, &Uint128::zero())?;
+    UNCLAIMED_REWARDS.save(deps.storage, &Uint128::zero())?;
     TOTAL_STAKED.save(deps.storage, &0u64)?;
+    LOCK_TIERS.save(deps.storage, &vec![])?;
+    MAX_LOCK_DURATION.save(deps.storage, &msg.duration)?;
+    UNBONDING_PERIOD.save(deps.storage, &0u64)?;
+    EMERGENCY.save(deps.storage, &false)?;
+    PAUSE_FLAGS.save(deps.storage, &PauseFlags::default())?;
+    PAUSER.save(deps.storage, &None)?;
+    VAULT.save(deps.storage, &None)?;
+    UNDELEGATION_PERIOD.save(deps.storage, &DEFAULT_UNDELEGATION_PERIOD)?;
+    HOOK_ERRORS_FATAL.save(deps.storage, &true)?;
+    TOTAL_POWER.save(deps.storage, &Uint128::zero(), env.block.height)?;
+    RECEIPT_COLLECTION.save(deps.storage, &None)?;
+    DENYLIST_MODE.save(deps.storage, &DenylistMode::Reject {  })?;
+    STREAK_TIERS.save(deps.storage, &vec![])?;
+    RENEWAL_WINDOW.save(deps.storage, &DEFAULT_RENEWAL_WINDOW)?;
+    TIMELOCK_DELAY.save(deps.storage, &DEFAULT_TIMELOCK_DELAY)?;
+    NEXT_CHANGE_ID.save(deps.storage, &0u64)?;
     
-    Ok(Response::default())
+    Ok(Response::new()
+        .add_attribute("action", "instantiate")
+        .add_event(util::event("instantiate")
+            .add_attribute("owner", config.owner)
+            .add_attribute("fee_address", config.fee_address)
+            .add_attribute("collection", config.collection_address)
+            .add_attribute("denom", config.native_token)
+            .add_attribute("duration", config.duration.to_string())
+            .add_attribute("locktime_fee", Uint128::from(1000000000000000000u128))
+        )
+    )
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -75,17 +183,19 @@ pub fn execute(
     match msg {
         ExecuteMsg::UpdateOwner { 
             owner 
-        } => util::execute_update_owner(
+        } => util::execute_queue_change(
             deps.storage, 
             info.sender, 
-            owner
+            &env.block,
+            ConfigChange::Owner { owner }
         ),
         ExecuteMsg::UpdateFeeAddress { 
             fee_address
-        } => util::execute_update_fee_address(
+        } => util::execute_queue_change(
             deps.storage, 
             info.sender, 
-            fee_address
+            &env.block,
+            ConfigChange::FeeAddress { fee_address }
         ),
         ExecuteMsg::UpdateEnabled { 
             enabled 
@@ -96,10 +206,11 @@ pub fn execute(
         ),
         ExecuteMsg::UpdateDuration {
             duration
-        } => util::execute_update_duration(
+        } => util::execute_queue_change(
             deps.storage, 
             info.sender, 
-            duration
+            &env.block,
+            ConfigChange::Duration { duration }
         ),
         ExecuteMsg::UpdateConfig {
             new_owner,
@@ -107,14 +218,40 @@ pub fn execute(
             new_collection_address,
             new_duration,
             new_locktime_fee,
-        } => util::execute_update_config(
+        } => util::execute_queue_change(
             deps.storage,
             info.sender,
-            new_owner,
-            new_fee_address,
-            new_collection_address,
-            new_duration,
-            new_locktime_fee
+            &env.block,
+            ConfigChange::Config {
+                new_owner: Some(new_owner),
+                new_fee_address: Some(new_fee_address),
+                new_collection_address: Some(new_collection_address),
+                new_duration: Some(new_duration),
+                new_locktime_fee: Some(new_locktime_fee)
+            }
+        ),
+        ExecuteMsg::UpdateTimelockDelay {
+            delay
+        } => util::execute_queue_change(
+            deps.storage, 
+            info.sender, 
+            &env.block,
+            ConfigChange::TimelockDelay { delay }
+        ),
+        ExecuteMsg::ExecuteChange {
+            id
+        } => util::execute_apply_change(
+            deps.storage, 
+            info.sender, 
+            &env.block,
+            id
+        ),
+        ExecuteMsg::CancelChange {
+            id
+        } => util::execute_cancel_change(
+            deps.storage, 
+            info.sender, 
+            id
         ),
         ExecuteMsg::Withdraw {
             amount,
@@ -132,6 +269,12 @@ pub fn execute(
             info, 
             airdrop_amount,
         ),
+        ExecuteMsg::FundAirdrop {
+        } => execute_fund_airdrop(
+            deps, 
+            env, 
+            info, 
+        ),
         ExecuteMsg::AirdropRestart {
         } => execute_airdrop_restart(
             deps, 
@@ -155,20 +298,305 @@ pub fn execute(
             unstake_nft_id
         ),
         ExecuteMsg::Claim {
-            claim_nft_id
+            claim_nft_id,
+            recipient,
+            owner
         } => execute_claim(
             deps, 
             env, 
             info, 
-            claim_nft_id
+            claim_nft_id,
+            recipient,
+            owner
         ),
         ExecuteMsg::Restake {
-            restake_nft_id
+            restake_nft_id,
+            owner
         } => execute_restake(
             deps, 
             env, 
             info, 
-            restake_nft_id
+            restake_nft_id,
+            owner
+        ),
+        ExecuteMsg::SetRewardRecipient {
+            recipient
+        } => execute_set_reward_recipient(
+            deps, 
+            info, 
+            recipient
+        ),
+        ExecuteMsg::ApproveAll {
+            operator,
+            expires
+        } => execute_approve_all(
+            deps, 
+            env, 
+            info, 
+            operator,
+            expires
+        ),
+        ExecuteMsg::RevokeAll {
+            operator
+        } => execute_revoke_all(
+            deps, 
+            info, 
+            operator
+        ),
+        ExecuteMsg::SetClaimMode {
+            mode
+        } => execute_set_claim_mode(
+            deps, 
+            info, 
+            mode
+        ),
+        ExecuteMsg::UpdateVault {
+            vault
+        } => util::execute_queue_change(
+            deps.storage, 
+            info.sender, 
+            &env.block,
+            ConfigChange::Vault { vault }
+        ),
+        ExecuteMsg::UpdateUndelegationPeriod {
+            undelegation_period
+        } => util::execute_update_undelegation_period(
+            deps.storage, 
+            info.sender, 
+            undelegation_period
+        ),
+        ExecuteMsg::Undelegate {
+            validator,
+            amount
+        } => execute_undelegate(
+            deps, 
+            env, 
+            info, 
+            validator,
+            amount
+        ),
+        ExecuteMsg::ClaimUndelegated {
+        } => execute_claim_undelegated(
+            deps, 
+            env, 
+            info, 
+        ),
+        ExecuteMsg::AddHook {
+            addr
+        } => execute_add_hook(
+            deps, 
+            info, 
+            addr
+        ),
+        ExecuteMsg::RemoveHook {
+            addr
+        } => execute_remove_hook(
+            deps, 
+            info, 
+            addr
+        ),
+        ExecuteMsg::UpdateStakingAgents {
+            add,
+            remove
+        } => util::execute_update_staking_agents(
+            deps.api, 
+            deps.storage, 
+            info.sender, 
+            add,
+            remove
+        ),
+        ExecuteMsg::CreateReceiptCollection {
+            code_id,
+            name,
+            symbol
+        } => execute_create_receipt_collection(
+            deps, 
+            env, 
+            info, 
+            code_id,
+            name,
+            symbol
+        ),
+        ExecuteMsg::SyncReceipt {
+            token_id
+        } => execute_sync_receipt(
+            deps, 
+            env, 
+            token_id
+        ),
+        ExecuteMsg::SyncVotingPower {
+            addresses
+        } => execute_sync_voting_power(
+            deps, 
+            env, 
+            addresses
+        ),
+        ExecuteMsg::TransferStake {
+            token_id,
+            to,
+            require_acceptance
+        } => execute_transfer_stake(
+            deps, 
+            env, 
+            info, 
+            token_id,
+            to,
+            require_acceptance
+        ),
+        ExecuteMsg::AcceptStake {
+            token_id
+        } => execute_accept_stake(
+            deps, 
+            env, 
+            info, 
+            token_id
+        ),
+        ExecuteMsg::CancelStakeTransfer {
+            token_id
+        } => execute_cancel_stake_transfer(
+            deps, 
+            info, 
+            token_id
+        ),
+        ExecuteMsg::UpdateDenylist {
+            add_tokens,
+            remove_tokens,
+            add_addresses,
+            remove_addresses
+        } => util::execute_update_denylist(
+            deps.api, 
+            deps.storage, 
+            info.sender, 
+            add_tokens,
+            remove_tokens,
+            add_addresses,
+            remove_addresses
+        ),
+        ExecuteMsg::UpdateDenylistMode {
+            mode
+        } => util::execute_update_denylist_mode(
+            deps.storage, 
+            info.sender, 
+            mode
+        ),
+        ExecuteMsg::UpdateStakingLimits {
+            max_per_user,
+            max_total_staked,
+            weighting
+        } => util::execute_queue_change(
+            deps.storage, 
+            info.sender, 
+            &env.block,
+            ConfigChange::StakingLimits { max_per_user, max_total_staked, weighting }
+        ),
+        ExecuteMsg::UpdateAirdropEligibility {
+            min_stake_age,
+            time_weighted
+        } => util::execute_update_airdrop_eligibility(
+            deps.storage, 
+            info.sender, 
+            min_stake_age,
+            time_weighted
+        ),
+        ExecuteMsg::UpdateStreakTiers {
+            tiers,
+            renewal_window
+        } => util::execute_update_streak_tiers(
+            deps.storage, 
+            info.sender, 
+            tiers,
+            renewal_window
+        ),
+        ExecuteMsg::UpdateHookErrorsFatal {
+            fatal
+        } => util::execute_update_hook_errors_fatal(
+            deps.storage, 
+            info.sender, 
+            fatal
+        ),
+        ExecuteMsg::SetAutoRenew {
+            token_ids,
+            enabled
+        } => execute_set_auto_renew(
+            deps, 
+            env, 
+            info, 
+            token_ids,
+            enabled
+        ),
+        ExecuteMsg::UpdateLockTiers {
+            tiers,
+            max_lock_duration
+        } => util::execute_queue_change(
+            deps.storage, 
+            info.sender, 
+            &env.block,
+            ConfigChange::LockTiers { tiers, max_lock_duration }
+        ),
+        ExecuteMsg::ExtendLock {
+            token_id,
+            additional_seconds,
+            tier,
+            owner
+        } => execute_extend_lock(
+            deps, 
+            env, 
+            info, 
+            token_id,
+            additional_seconds,
+            tier,
+            owner
+        ),
+        ExecuteMsg::UpdateUnbondingPeriod {
+            unbonding_period
+        } => util::execute_queue_change(
+            deps.storage, 
+            info.sender, 
+            &env.block,
+            ConfigChange::UnbondingPeriod { unbonding_period }
+        ),
+        ExecuteMsg::WithdrawNft {
+        } => execute_withdraw_nft(
+            deps, 
+            env, 
+            info, 
+        ),
+        ExecuteMsg::SetEmergency {
+            enabled
+        } => util::execute_set_emergency(
+            deps.storage, 
+            info.sender, 
+            enabled
+        ),
+        ExecuteMsg::EmergencyWithdraw {
+            token_ids
+        } => execute_emergency_withdraw(
+            deps, 
+            env, 
+            info, 
+            token_ids
+        ),
+        ExecuteMsg::EmergencyReturnAll {
+            limit
+        } => execute_emergency_return_all(
+            deps, 
+            env, 
+            info, 
+            limit
+        ),
+        ExecuteMsg::UpdatePauser {
+            pauser
+        } => util::execute_update_pauser(
+            deps.storage, 
+            info.sender, 
+            pauser
+        ),
+        ExecuteMsg::UpdatePauseFlags {
+            flags
+        } => util::execute_update_pause_flags(
+            deps.storage, 
+            info.sender, 
+            flags
         ),
     }
 }
@@ -183,16 +611,22 @@ pub fn execute_withdraw (
 
     let cfg = CONFIG.load(deps.storage)?;
 
-    if util::get_token_amount(deps.querier, Denom::Native(cfg.native_token.clone()), env.clone().contract.address.clone())? < amount {
+    // Rewards owed to stakers stay in the contract.
+    let reserved = util::get_reserved_rewards(deps.storage)?;
+    if util::get_token_amount(deps.querier, Denom::Native(cfg.native_token.clone()), env.clone().contract.address.clone())? < amount + reserved {
         return Err(crate::ContractError::InsufficientCw20 {  });
     }
 
-    let msg = util::transfer_token_message(Denom::Native(cfg.native_token.clone()), amount.clone(), info.sender.clone())?;
+    let msg = util::transfer_token_message(Denom::Native(cfg.native_token.clone()), amount, info.sender.clone())?;
 
     Ok(Response::new()
         .add_message(msg)
-        .add_attribute("action", "execute_withdraw")
-        .add_attribute("withdraw", amount.clone())
+        .add_attribute("action", "withdraw")
+        .add_event(util::event("withdraw")
+            .add_attribute("user", info.sender)
+            .add_attribute("amount", amount)
+            .add_attribute("denom", cfg.native_token)
+        )
     )
 }
 
@@ -202,7 +636,8 @@ pub fn execute_airdrop(
     info: MessageInfo,
     airdrop_amount: Uint128
 ) -> Result<Response, ContractError> { 
-    util::check_enabled(deps.storage)?;
+    util::check_enabled(deps.storage, util::Operation::Airdrop)?;
+    util::check_not_emergency(deps.storage)?;
     util::check_owner(deps.storage, info.sender.clone())?;
 
     if airdrop_amount <= Uint128::zero() {
@@ -211,58 +646,64 @@ pub fn execute_airdrop(
 
     let cfg = CONFIG.load(deps.storage)?;
 
-    if util::get_token_amount(deps.querier, Denom::Native(cfg.native_token.clone()), env.clone().contract.address.clone())? < airdrop_amount {
+    // The airdrop hands out the carried dust too, on top of what is owed.
+    let reserved = util::get_reserved_rewards(deps.storage)?;
+    if util::get_token_amount(deps.querier, Denom::Native(cfg.native_token.clone()), env.clone().contract.address.clone())? < airdrop_amount + reserved {
         return Err(crate::ContractError::InsufficientCw20 {  });
     }
 
-    let nft_count = util::get_in_locktime_nft_count(deps.storage, env.block.clone(), cfg.collection_address.clone())?;
+    let (airdrop, credited, remainder, total_weight, credits) = util::distribute_airdrop(deps.storage, env.block.clone(), airdrop_amount)?;
 
-    if nft_count.is_zero() {
-        return Err(crate::ContractError::NoUnexpiredNft {  });
-    }
+    START_AIRDROP.update(deps.storage, | _| -> StdResult<_> {
+        Ok(false)
+    })?;
 
-    if airdrop_amount < nft_count {
-        return Err(crate::ContractError::OverNftCount { 
-            nft_count 
-        });
-    }
+    Ok(Response::new()
+        .add_attribute("action", "airdrop")
+        .add_event(util::event("airdrop")
+            .add_attribute("user", info.sender)
+            .add_attribute("amount", airdrop_amount)
+            .add_attribute("denom", cfg.native_token)
+            .add_attribute("share", airdrop)
+            .add_attribute("total_weight", total_weight)
+            .add_attribute("distributed", credited)
+            .add_attribute("remainder", remainder)
+        )
+        .add_events(credits)
+    )
+}
+
+/// Distributes the native reward attached to the message. Anyone may
+/// sponsor an airdrop this way; the running total per sponsor is recorded.
+pub fn execute_fund_airdrop(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> { 
+    util::check_enabled(deps.storage, util::Operation::Airdrop)?;
+    util::check_not_emergency(deps.storage)?;
 
-    let airdrop = Uint128::from(airdrop_amount/nft_count);
+    let cfg = CONFIG.load(deps.storage)?;
+    let airdrop_amount = must_pay(&info, &cfg.native_token)?;
 
-    let result: StdResult<Vec<(Addr, UserInfo)>> = ACCOUNT_MAP.range(deps.storage, None, None, Order::Ascending).collect();
-    
-    match result {
-        Ok(all_accounts) => {
-            for (_address, _userinfo) in all_accounts.iter() {
-                let mut userinfo = _userinfo.clone();
-                for (index, _nftinfo) in userinfo.staked_nfts.clone().iter().enumerate() {
-                    let mut nftinfo = _nftinfo.clone();
-                    if nftinfo.lock_time > env.block.time.seconds() {
-                        nftinfo.airdrop += airdrop;
-                    }
-                    userinfo.staked_nfts[index] = nftinfo;
-                };
-                ACCOUNT_MAP.save(deps.storage, _address.clone(), &userinfo)?;
-            }
+    let (airdrop, credited, remainder, total_weight, credits) = util::distribute_airdrop(deps.storage, env.block.clone(), airdrop_amount)?;
 
-            TOTAL_AIRDROP.update(deps.storage, |mut exists| -> StdResult<_> {
-                exists += airdrop_amount;
-                Ok(exists)
-            })?;
-        
-            START_AIRDROP.update(deps.storage, | _| -> StdResult<_> {
-                Ok(false)
-            })?;
-            
-        },
-        Err(_error) => {
-            return Err(crate::ContractError::NoAirdropNft {  });
-        }
-    }
+    AIRDROP_SPONSORS.update(deps.storage, info.sender.clone(), |exists| -> StdResult<_> {
+        Ok(exists.unwrap_or_default() + airdrop_amount)
+    })?;
 
     Ok(Response::new()
-        .add_attribute("action", "execute_airdrop")
-        .add_attribute("airdrop", airdrop.clone())
+        .add_attribute("action", "fund_airdrop")
+        .add_event(util::event("fund_airdrop")
+            .add_attribute("user", info.sender)
+            .add_attribute("amount", airdrop_amount)
+            .add_attribute("denom", cfg.native_token)
+            .add_attribute("share", airdrop)
+            .add_attribute("total_weight", total_weight)
+            .add_attribute("distributed", credited)
+            .add_attribute("remainder", remainder)
+        )
+        .add_events(credits)
     )
 }
 
@@ -280,7 +721,11 @@ pub fn execute_airdrop_restart(
     START_AIRDROP.update(deps.storage, |_| -> StdResult<_> {Ok(true)})?;
 
     Ok(Response::new()
-        .add_attribute("action", "execute_airdrop_restart")
+        .add_attribute("action", "airdrop_restart")
+        .add_event(util::event("airdrop_restart")
+            .add_attribute("height", env.block.height.to_string())
+            .add_attribute("time", env.block.time.seconds().to_string())
+        )
     )
 }
 
@@ -290,7 +735,12 @@ pub fn execute_receive_nft(
     info: MessageInfo,
     wrapper: Cw721ReceiveMsg
 ) -> Result<Response, ContractError> {
-    util::check_enabled(deps.storage)?;
+    if RECEIPT_COLLECTION.load(deps.storage)?.as_ref() == Some(&info.sender) {
+        return execute_redeem_receipt(deps, env, info, wrapper);
+    }
+
+    util::check_enabled(deps.storage, util::Operation::Stake)?;
+    util::check_not_emergency(deps.storage)?;
     util::check_airdrop_start(deps.storage)?;
 
     let cfg = CONFIG.load(deps.storage)?;
@@ -302,60 +752,205 @@ pub fn execute_receive_nft(
     let stake_nft_id = wrapper.token_id.clone();
     let user_addr = deps.api.addr_validate(wrapper.sender.as_str())?;
 
-    let msg: NftReceiveMsg = from_binary(&wrapper.msg)?;
+    let msg: NftReceiveMsg = if wrapper.msg.is_empty() {
+        NftReceiveMsg::StakeV2 { lock_tier: None, auto_renew: None, reward_recipient: None, beneficiary: None, receipt: None }
+    } else {
+        from_binary(&wrapper.msg)?
+    };
 
-    match msg {
+    let (lock_tier, auto_renew, reward_recipient, beneficiary, receipt) = match msg {
         NftReceiveMsg::Stake {
             sender,
-            token_id
+            token_id,
+            auto_renew
         } => {
             if (sender != user_addr) || (token_id != stake_nft_id) {
                 return Err(ContractError::InvalidCw721Msg {  });
             }
+            (None, auto_renew, None, None, None)
+        },
+        NftReceiveMsg::StakeV2 {
+            lock_tier,
+            auto_renew,
+            reward_recipient,
+            beneficiary,
+            receipt
+        } => (lock_tier, auto_renew, reward_recipient, beneficiary, receipt),
+    };
 
-            let duration = cfg.duration;
-            let _nftinfo = NftInfo {
-                nft_id: stake_nft_id.clone(),
-                lock_time: duration+env.block.time.seconds(),
-                airdrop: Uint128::zero(),
-                collection_address: cfg.collection_address.clone()
-            };
-            
-            let mut _userinfo = UserInfo {
-                address: info.sender.clone(),
-                staked_nfts: vec![_nftinfo.clone()],
-                total_earnd: Uint128::zero()
-            };
+    let staker = match beneficiary {
+        Some(beneficiary) if beneficiary != user_addr => {
+            // The reward recipient applies to the whole account, so only
+            // the account itself may set it.
+            if !STAKING_AGENTS.has(deps.storage, user_addr) || reward_recipient.is_some() {
+                return Err(ContractError::Unauthorized {  });
+            }
+            deps.api.addr_validate(beneficiary.as_str())?
+        },
+        _ => user_addr,
+    };
+
+    util::check_not_denied(deps.storage, &staker, &stake_nft_id)?;
+    let user_count = ACCOUNT_MAP.may_load(deps.storage, staker.clone())?.map_or(0, |userinfo| userinfo.staked_nfts.len());
+    util::check_staking_limits(deps.storage, &cfg, user_count)?;
 
-            if ACCOUNT_MAP.has(deps.storage, user_addr.clone()) {
-                _userinfo = ACCOUNT_MAP.load(deps.storage, user_addr.clone())?;
-                _userinfo.staked_nfts.push(_nftinfo.clone());
+    let lock_duration = match lock_tier {
+        Some(tier) => {
+            let tiers = LOCK_TIERS.load(deps.storage)?;
+            match tiers.get(tier as usize) {
+                Some(tier) if tier.duration >= cfg.duration => tier.duration,
+                _ => return Err(ContractError::InvalidTier {  }),
             }
+        },
+        None => cfg.duration,
+    };
 
-            ACCOUNT_MAP.save(deps.storage, user_addr.clone(), &_userinfo)?;
-            TOTAL_STAKED.update(deps.storage, | exists| -> StdResult<_> {
-                Ok(exists+1)
-            })?;
+    if let Some(recipient) = reward_recipient {
+        let recipient = deps.api.addr_validate(recipient.as_str())?;
+        REWARD_RECIPIENT.save(deps.storage, staker.clone(), &recipient)?;
+    }
 
-            Ok(Response::new()
-                .add_attribute("action", "execute_stake")
-                .add_attribute("nft_id", stake_nft_id.clone())
-            )
+    let receipt = receipt.unwrap_or(false);
+    let mut msgs: Vec<CosmosMsg> = vec![];
+    let mut receipt_id = None;
+    if receipt {
+        let collection = RECEIPT_COLLECTION.load(deps.storage)?.ok_or(ContractError::NoReceiptCollection {  })?;
+        let id = util::new_receipt_id(deps.storage, &stake_nft_id)?;
+        msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: collection.to_string(),
+            msg: to_binary(&ReceiptExecuteMsg::Mint {
+                token_id: id.clone(),
+                owner: staker.to_string(),
+                token_uri: None,
+                extension: Empty {  },
+            })?,
+            funds: vec![],
+        }));
+        RECEIPT_HOLDERS.save(deps.storage, stake_nft_id.clone(), &staker)?;
+        receipt_id = Some(id);
+    }
+
+    let _nftinfo = NftInfo {
+        nft_id: stake_nft_id,
+        lock_time: lock_duration+env.block.time.seconds(),
+        airdrop: Uint128::zero(),
+        collection_address: cfg.collection_address.clone(),
+        auto_renew: auto_renew.unwrap_or(false),
+        receipt,
+        staked_at: env.block.time.seconds(),
+        streak: 0,
+        duration: Some(lock_duration),
+        locktime_fee: Some(LOCKTIME_FEE.load(deps.storage)?),
+        multiplier: Some(util::get_lock_multiplier(&LOCK_TIERS.load(deps.storage)?, lock_duration)),
+    };
+
+    let mut _userinfo = UserInfo {
+        address: staker.clone(),
+        staked_nfts: vec![_nftinfo.clone()],
+        total_earnd: Uint128::zero()
+    };
+
+    if ACCOUNT_MAP.has(deps.storage, staker.clone()) {
+        _userinfo = ACCOUNT_MAP.load(deps.storage, staker.clone())?;
+        _userinfo.staked_nfts.push(_nftinfo.clone());
+    }
+
+    ACCOUNT_MAP.save(deps.storage, staker.clone(), &_userinfo)?;
+    util::sync_voting_power(deps.storage, &env.block, &staker)?;
+    TOTAL_STAKED.update(deps.storage, | exists| -> StdResult<_> {
+        Ok(exists+1)
+    })?;
+
+    let hook_msgs = util::stake_hook_msgs(deps.storage, StakeChangeKind::Stake, &staker, &_nftinfo, _userinfo.staked_nfts.len())?;
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_submessages(hook_msgs)
+        .add_attribute("action", "stake")
+        .add_event(util::nft_event("stake", &staker, &_nftinfo)
+            .add_attribute("sender", wrapper.sender)
+            .add_attributes(receipt_id.map(|id| ("receipt_id", id)))
+        )
+    )
+}
+
+/// Burns a receipt sent back by its holder. A live receipt first moves its
+/// position to the sender, who then holds it directly; a receipt voided by
+/// an emergency return is only burned.
+pub fn execute_redeem_receipt(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    wrapper: Cw721ReceiveMsg
+) -> Result<Response, ContractError> {
+    let sender = deps.api.addr_validate(&wrapper.sender)?;
+    let receipt_id = wrapper.token_id;
+    let burn = CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: info.sender.to_string(),
+        msg: to_binary(&ReceiptExecuteMsg::Burn {
+            token_id: receipt_id.clone(),
+        })?,
+        funds: vec![],
+    });
+
+    let token_id = match RECEIPT_TOKENS.may_load(deps.storage, receipt_id.clone())? {
+        Some(token_id) => token_id,
+        None => {
+            return Ok(Response::new()
+                .add_message(burn)
+                .add_attribute("action", "burn_void_receipt")
+                .add_event(util::event("burn_void_receipt")
+                    .add_attribute("user", sender)
+                    .add_attribute("receipt_id", receipt_id)
+                )
+            );
         }
+    };
+
+    let mut events = vec![];
+    let mut hook_msgs = vec![];
+    let holder = RECEIPT_HOLDERS.load(deps.storage, token_id.clone())?;
+    if holder != sender {
+        util::check_can_receive(deps.storage, &sender, &token_id)?;
+        let (event, move_hooks) = util::move_position(deps.storage, &env.block, &holder, &sender, &token_id)?;
+        events.push(event);
+        hook_msgs = move_hooks;
     }
+    util::clear_receipt(deps.storage, &token_id, false)?;
+
+    let mut userinfo = ACCOUNT_MAP.load(deps.storage, sender.clone())?;
+    let nftinfo = userinfo.staked_nfts.iter_mut()
+        .find(|nft| nft.nft_id == token_id)
+        .ok_or(ContractError::NoStakedNft {  })?;
+    nftinfo.receipt = false;
+    events.push(util::nft_event("redeem_receipt", &sender, nftinfo)
+        .add_attribute("receipt_id", receipt_id)
+    );
+    ACCOUNT_MAP.save(deps.storage, sender, &userinfo)?;
+
+    Ok(Response::new()
+        .add_message(burn)
+        .add_submessages(hook_msgs)
+        .add_attribute("action", "redeem_receipt")
+        .add_events(events)
+    )
 }
 
 pub fn execute_restake(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
-    restake_nft_id: String
+    restake_nft_id: String,
+    owner: Option<Addr>
 ) -> Result<Response, ContractError> {
-    util::check_enabled(deps.storage)?;
+    util::check_enabled(deps.storage, util::Operation::Restake)?;
+    util::check_not_emergency(deps.storage)?;
     util::check_airdrop_start(deps.storage)?;
 
+    let staker = util::resolve_staker(deps.storage, &env.block, info.sender, owner)?;
+    let (synced, sync_hooks) = util::sync_receipt(deps.storage, deps.querier, &env.block, &restake_nft_id)?;
     let cfg = CONFIG.load(deps.storage)?;
-    let mut userinfo = ACCOUNT_MAP.load(deps.storage, info.sender.clone())?;
+    let mut userinfo = ACCOUNT_MAP.load(deps.storage, staker.clone())?;
 
     if userinfo.staked_nfts.is_empty() {
         return Err(ContractError::NoStakedNft {  });
@@ -371,95 +966,944 @@ pub fn execute_restake(
                 return Err(ContractError::InvalidCw721Msg {  });
             }
 
-            if nftinfo.lock_time > env.block.time.seconds() {
+            if util::get_lock_time(&nftinfo, cfg.duration, env.block.time.seconds()) > env.block.time.seconds() {
                 return Err(ContractError::Locktime {  });
             }
 
+            // A restake inside the renewal window continues the streak of
+            // the NFT and of the account; a late one starts both over. The
+            // account streak rises once per period, however many NFTs renew.
+            let renewal_window = RENEWAL_WINDOW.load(deps.storage)?;
+            let now = env.block.time.seconds();
+            let on_time = now - nftinfo.lock_time <= renewal_window;
+            let user_streak = if on_time {
+                nftinfo.streak += 1;
+                let stored = USER_STREAKS.may_load(deps.storage, staker.clone())?.unwrap_or_default();
+                let user_streak = util::get_user_streak(stored, &userinfo.staked_nfts, renewal_window, now);
+                let counted_until = USER_STREAK_PERIODS.may_load(deps.storage, staker.clone())?.unwrap_or_default();
+                if user_streak == 0 || now >= counted_until {
+                    USER_STREAK_PERIODS.save(deps.storage, staker.clone(), &(now + cfg.duration))?;
+                    user_streak + 1
+                } else {
+                    user_streak
+                }
+            } else {
+                nftinfo.streak = 0;
+                USER_STREAK_PERIODS.remove(deps.storage, staker.clone());
+                0
+            };
+            USER_STREAKS.save(deps.storage, staker.clone(), &user_streak)?;
+
+            // The restake renews the position on the current terms.
             nftinfo.lock_time = env.block.time.seconds()+cfg.duration;
+            nftinfo.multiplier = Some(util::get_lock_multiplier(&LOCK_TIERS.load(deps.storage)?, cfg.duration));
+            nftinfo.duration = Some(cfg.duration);
+            nftinfo.locktime_fee = Some(LOCKTIME_FEE.load(deps.storage)?);
+            let hook_msgs = util::stake_hook_msgs(deps.storage, StakeChangeKind::Restake, &staker, &nftinfo, userinfo.staked_nfts.len())?;
+            let event = util::nft_event("restake", &staker, &nftinfo)
+                .add_attribute("streak", nftinfo.streak.to_string())
+                .add_attribute("user_streak", user_streak.to_string());
             userinfo.staked_nfts[index] = nftinfo;
-            ACCOUNT_MAP.save(deps.storage, info.sender.clone(), &userinfo)?;
+            ACCOUNT_MAP.save(deps.storage, staker.clone(), &userinfo)?;
+            util::sync_voting_power(deps.storage, &env.block, &staker)?;
 
             Ok(Response::new()
+                .add_submessages(hook_msgs)
                 .add_attribute("action", "restake")
+                .add_submessages(sync_hooks)
+                .add_events(synced)
+                .add_event(event)
             )
         },
         None => {
-            return Err(ContractError::NoStakedNft {  });
+            Err(ContractError::NoStakedNft {  })
         }
     }
 }
 
-pub fn execute_unstake(
+pub fn execute_extend_lock(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
-    unstake_nft_id: String
+    token_id: String,
+    additional_seconds: Option<u64>,
+    tier: Option<u32>,
+    owner: Option<Addr>
 ) -> Result<Response, ContractError> {
-    util::check_enabled(deps.storage)?;
+    util::check_enabled(deps.storage, util::Operation::Restake)?;
+    util::check_not_emergency(deps.storage)?;
+    util::check_airdrop_start(deps.storage)?;
 
+    let staker = util::resolve_staker(deps.storage, &env.block, info.sender, owner)?;
+    let (synced, sync_hooks) = util::sync_receipt(deps.storage, deps.querier, &env.block, &token_id)?;
     let cfg = CONFIG.load(deps.storage)?;
-    let locktime_fee = LOCKTIME_FEE.load(deps.storage)?;
-    let mut userinfo = ACCOUNT_MAP.load(deps.storage, info.sender.clone())?;
+    let max_lock_duration = MAX_LOCK_DURATION.load(deps.storage)?;
+    let mut userinfo = ACCOUNT_MAP.load(deps.storage, staker.clone())?;
 
-    if userinfo.staked_nfts.is_empty() {
-        return Err(ContractError::NoStakedNft {  });
-    }
-    
-    let index = userinfo.staked_nfts.iter().position(|nft| nft.nft_id == unstake_nft_id);
+    let index = userinfo.staked_nfts.iter().position(|nft| nft.nft_id == token_id);
 
     match index {
         Some(index) => {
-            let nftinfo = userinfo.staked_nfts[index].clone();
-            let mut msgs:Vec<CosmosMsg> = vec![];
-
-            if nftinfo.lock_time > env.block.time.seconds() {
-                let receive_fee = match must_pay(&info, &cfg.native_token.clone()) {
-                    Ok(it) => it,
-                    Err(_err) => return Err(ContractError::Locktime {  }),
-                }.u128();
+            let mut nftinfo = userinfo.staked_nfts[index].clone();
 
-                if receive_fee >= u128::from(locktime_fee) {
-                    let fee_msg = util::transfer_token_message(Denom::Native(cfg.native_token.clone()), locktime_fee.clone(), cfg.fee_address.clone())?;
-                    msgs.push(fee_msg)
-                } else {
-                    return Err(ContractError::Locktime {  });
-                }
+            if nftinfo.collection_address != cfg.collection_address {
+                return Err(ContractError::InvalidCw721Msg {  });
             }
-            
-            msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: cfg.collection_address.clone().to_string(),
-                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
-                    token_id: unstake_nft_id,
-                    recipient: info.sender.clone().into()
-                })?,
-                funds: vec![],
-            }));
 
-            userinfo.staked_nfts.remove(index);
-            ACCOUNT_MAP.save(deps.storage, info.sender.clone(), &userinfo)?;
+            let now = env.block.time.seconds();
+            let current_lock = util::get_lock_time(&nftinfo, cfg.duration, now).max(now);
+            let new_lock = match (additional_seconds, tier) {
+                (Some(additional_seconds), None) => current_lock.checked_add(additional_seconds).ok_or(ContractError::InvalidExtension {  })?,
+                (None, Some(tier)) => {
+                    let tiers = LOCK_TIERS.load(deps.storage)?;
+                    match tiers.get(tier as usize) {
+                        Some(tier) => {
+                            nftinfo.duration = Some(tier.duration);
+                            now + tier.duration
+                        },
+                        None => return Err(ContractError::InvalidTier {  }),
+                    }
+                },
+                _ => return Err(ContractError::InvalidExtension {  }),
+            };
+
+            if new_lock <= current_lock {
+                return Err(ContractError::InvalidExtension {  });
+            }
+
+            if new_lock - now > max_lock_duration {
+                return Err(ContractError::OverMaxLock { max_lock_duration });
+            }
+
+            nftinfo.lock_time = new_lock;
+            nftinfo.multiplier = Some(util::get_lock_multiplier(&LOCK_TIERS.load(deps.storage)?, new_lock - now));
+            let event = util::nft_event("extend_lock", &staker, &nftinfo);
+            userinfo.staked_nfts[index] = nftinfo;
+            ACCOUNT_MAP.save(deps.storage, staker.clone(), &userinfo)?;
+            util::sync_voting_power(deps.storage, &env.block, &staker)?;
+
+            Ok(Response::new()
+                .add_attribute("action", "extend_lock")
+                .add_submessages(sync_hooks)
+                .add_events(synced)
+                .add_event(event)
+            )
+        },
+        None => {
+            Err(ContractError::NoStakedNft {  })
+        }
+    }
+}
+
+pub fn execute_unstake(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    unstake_nft_id: String
+) -> Result<Response, ContractError> {
+    util::check_enabled(deps.storage, util::Operation::Unstake)?;
+    let (synced, sync_hooks) = util::sync_receipt(deps.storage, deps.querier, &env.block, &unstake_nft_id)?;
+
+    let cfg = CONFIG.load(deps.storage)?;
+    let locktime_fee = LOCKTIME_FEE.load(deps.storage)?;
+    let mut userinfo = ACCOUNT_MAP.load(deps.storage, info.sender.clone())?;
+
+    if userinfo.staked_nfts.is_empty() {
+        return Err(ContractError::NoStakedNft {  });
+    }
+    
+    let index = userinfo.staked_nfts.iter().position(|nft| nft.nft_id == unstake_nft_id);
+
+    match index {
+        Some(index) => {
+            let nftinfo = userinfo.staked_nfts[index].clone();
+            // The receipt has to be redeemed first, so it is burned.
+            if nftinfo.receipt {
+                return Err(ContractError::ReceiptBacked {  });
+            }
+            let locktime_fee = nftinfo.locktime_fee.unwrap_or(locktime_fee);
+            let mut msgs:Vec<CosmosMsg> = vec![];
+            let mut refund = may_pay(&info, &cfg.native_token)?;
+            let mut fee = Uint128::zero();
+
+            if util::get_lock_time(&nftinfo, cfg.duration, env.block.time.seconds()) > env.block.time.seconds() {
+                if refund >= locktime_fee {
+                    let fee_msg = util::transfer_token_message(Denom::Native(cfg.native_token.clone()), locktime_fee, cfg.fee_address.clone())?;
+                    msgs.push(fee_msg);
+                    refund -= locktime_fee;
+                    fee = locktime_fee;
+                } else {
+                    return Err(ContractError::Locktime {  });
+                }
+            }
+
+            // Anything sent beyond the fee that is due goes back to the sender.
+            if !refund.is_zero() {
+                msgs.push(util::transfer_token_message(Denom::Native(cfg.native_token.clone()), refund, info.sender.clone())?);
+            }
+            
+            let unbonding_period = UNBONDING_PERIOD.load(deps.storage)?;
+            let release_at = env.block.time.seconds().checked_add(unbonding_period)
+                .ok_or(ContractError::InvalidUnbondingPeriod { max_unbonding_period: util::MAX_UNBONDING_PERIOD })?;
+            if unbonding_period > 0 {
+                let mut claims = NFT_CLAIMS.may_load(deps.storage, info.sender.clone())?.unwrap_or_default();
+                claims.push(NftClaim {
+                    nft_id: unstake_nft_id,
+                    collection_address: nftinfo.collection_address.clone(),
+                    release_at
+                });
+                NFT_CLAIMS.save(deps.storage, info.sender.clone(), &claims)?;
+            } else {
+                msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: nftinfo.collection_address.to_string(),
+                    msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                        token_id: unstake_nft_id,
+                        recipient: info.sender.clone().into()
+                    })?,
+                    funds: vec![],
+                }));
+            }
+
+            PENDING_TRANSFERS.remove(deps.storage, nftinfo.nft_id.clone());
+            userinfo.staked_nfts.remove(index);
+            ACCOUNT_MAP.save(deps.storage, info.sender.clone(), &userinfo)?;
+            util::sync_voting_power(deps.storage, &env.block, &info.sender)?;
+            util::release_rewards(deps.storage, nftinfo.airdrop)?;
+            USER_STREAKS.remove(deps.storage, info.sender.clone());
+            USER_STREAK_PERIODS.remove(deps.storage, info.sender.clone());
+            TOTAL_STAKED.update(deps.storage, | exists| -> StdResult<_> {
+                Ok(exists.saturating_sub(1))
+            })?;
+            let hook_msgs = util::stake_hook_msgs(deps.storage, StakeChangeKind::Unstake, &info.sender, &nftinfo, userinfo.staked_nfts.len())?;
 
             Ok(Response::new()
                 .add_messages(msgs)
+                .add_submessages(hook_msgs)
                 .add_attribute("action", "unstake")
+                .add_submessages(sync_hooks)
+                .add_events(synced)
+                .add_event(util::nft_event("unstake", &info.sender, &nftinfo)
+                    .add_attribute("fee", fee)
+                    .add_attribute("refund", refund)
+                    .add_attribute("denom", cfg.native_token)
+                    .add_attribute("forfeited", nftinfo.airdrop)
+                    .add_attribute("release_at", release_at.to_string())
+                )
             )
         },
         None => {
-            return Err(ContractError::NoStakedNft {  });
+            Err(ContractError::NoStakedNft {  })
         }
     }
 }
 
-pub fn execute_claim(
+pub fn execute_set_auto_renew(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
-    claim_nft_id: String
+    token_ids: Vec<String>,
+    enabled: bool
 ) -> Result<Response, ContractError> {
-    util::check_enabled(deps.storage)?;
+    util::check_enabled(deps.storage, util::Operation::Restake)?;
+    util::check_not_emergency(deps.storage)?;
 
     let cfg = CONFIG.load(deps.storage)?;
+    let renewal_window = RENEWAL_WINDOW.load(deps.storage)?;
+    let now = env.block.time.seconds();
+    let mut events = vec![];
+    let mut hook_msgs: Vec<SubMsg> = vec![];
+    for token_id in token_ids.iter() {
+        let (synced, sync_hooks) = util::sync_receipt(deps.storage, deps.querier, &env.block, token_id)?;
+        events.extend(synced);
+        hook_msgs.extend(sync_hooks);
+    }
+    let mut userinfo = ACCOUNT_MAP.load(deps.storage, info.sender.clone())?;
+
+    for token_id in token_ids.iter() {
+        let index = userinfo.staked_nfts.iter().position(|nft| &nft.nft_id == token_id);
+
+        match index {
+            Some(index) => {
+                let mut nftinfo = userinfo.staked_nfts[index].clone();
+                // Pin the current period before switching off, so the lock
+                // that is already running is not cut short.
+                nftinfo.streak = util::get_streak(&nftinfo, cfg.duration, renewal_window, now);
+                nftinfo.lock_time = util::get_lock_time(&nftinfo, cfg.duration, now);
+                // Switching on a lock that lapsed past the renewal window
+                // starts a fresh period, like a late restake, rather than
+                // counting every period since as renewed.
+                if enabled && now - nftinfo.lock_time.min(now) > renewal_window {
+                    nftinfo.streak = 0;
+                    nftinfo.lock_time = now + nftinfo.duration.unwrap_or(cfg.duration);
+                    USER_STREAKS.save(deps.storage, info.sender.clone(), &0)?;
+                    USER_STREAK_PERIODS.remove(deps.storage, info.sender.clone());
+                }
+                nftinfo.auto_renew = enabled;
+                events.push(util::nft_event("set_auto_renew", &info.sender, &nftinfo));
+                userinfo.staked_nfts[index] = nftinfo;
+            },
+            None => {
+                return Err(ContractError::NoStakedNft {  });
+            }
+        }
+    }
+
+    ACCOUNT_MAP.save(deps.storage, info.sender.clone(), &userinfo)?;
+    util::sync_voting_power(deps.storage, &env.block, &info.sender)?;
+
+    Ok(Response::new()
+        .add_submessages(hook_msgs)
+        .add_attribute("action", "set_auto_renew")
+        .add_events(events)
+    )
+}
+
+pub fn execute_withdraw_nft(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let emergency = EMERGENCY.load(deps.storage)?;
+    if !emergency {
+        util::check_enabled(deps.storage, util::Operation::Unstake)?;
+    }
+
+    let claims = NFT_CLAIMS.may_load(deps.storage, info.sender.clone())?.unwrap_or_default();
+    let (matured, pending): (Vec<NftClaim>, Vec<NftClaim>) = claims
+        .into_iter()
+        .partition(|claim| emergency || claim.release_at <= env.block.time.seconds());
+
+    if matured.is_empty() {
+        return Err(ContractError::NoMaturedClaims {  });
+    }
+
+    if pending.is_empty() {
+        NFT_CLAIMS.remove(deps.storage, info.sender.clone());
+    } else {
+        NFT_CLAIMS.save(deps.storage, info.sender.clone(), &pending)?;
+    }
+
+    let mut msgs: Vec<CosmosMsg> = vec![];
+    let mut events = vec![];
+    for claim in matured.iter() {
+        events.push(util::event("withdraw_nft")
+            .add_attribute("user", &info.sender)
+            .add_attribute("collection", &claim.collection_address)
+            .add_attribute("token_id", claim.nft_id.clone())
+        );
+        msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: claim.collection_address.to_string(),
+            msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                token_id: claim.nft_id.clone(),
+                recipient: info.sender.clone().into()
+            })?,
+            funds: vec![],
+        }));
+    }
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_attribute("action", "withdraw_nft")
+        .add_events(events)
+    )
+}
+
+pub fn execute_emergency_withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_ids: Option<Vec<String>>
+) -> Result<Response, ContractError> {
+    if !EMERGENCY.load(deps.storage)? {
+        return Err(ContractError::NotEmergency {  });
+    }
+
+    let mut events = vec![];
+    let mut hook_msgs: Vec<SubMsg> = vec![];
+    let token_ids = match token_ids {
+        Some(token_ids) => {
+            for token_id in token_ids.iter() {
+                let (synced, sync_hooks) = util::sync_receipt(deps.storage, deps.querier, &env.block, token_id)?;
+                events.extend(synced);
+                hook_msgs.extend(sync_hooks);
+            }
+            token_ids
+        },
+        None => {
+            let staked_nfts = ACCOUNT_MAP.load(deps.storage, info.sender.clone())?.staked_nfts;
+            let mut token_ids = vec![];
+            for nftinfo in staked_nfts.iter() {
+                // Receipt-backed positions are left until the receipt is redeemed.
+                if !nftinfo.receipt {
+                    token_ids.push(nftinfo.nft_id.clone());
+                }
+            }
+            token_ids
+        },
+    };
     let mut userinfo = ACCOUNT_MAP.load(deps.storage, info.sender.clone())?;
 
+    if token_ids.is_empty() {
+        return Err(ContractError::NoStakedNft {  });
+    }
+
+    let mut msgs: Vec<CosmosMsg> = vec![];
+    for token_id in token_ids.iter() {
+        let index = userinfo.staked_nfts.iter().position(|nft| &nft.nft_id == token_id);
+
+        match index {
+            Some(index) => {
+                if userinfo.staked_nfts[index].receipt {
+                    return Err(ContractError::ReceiptBacked {  });
+                }
+                let nftinfo = userinfo.staked_nfts.remove(index);
+                util::release_rewards(deps.storage, nftinfo.airdrop)?;
+                PENDING_TRANSFERS.remove(deps.storage, nftinfo.nft_id.clone());
+                events.push(util::nft_event("emergency_withdraw", &info.sender, &nftinfo)
+                    .add_attribute("forfeited", nftinfo.airdrop)
+                );
+                hook_msgs.extend(util::emergency_hook_msgs(deps.storage, &info.sender, &nftinfo, userinfo.staked_nfts.len())?);
+                msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: nftinfo.collection_address.to_string(),
+                    msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                        token_id: nftinfo.nft_id,
+                        recipient: info.sender.clone().into()
+                    })?,
+                    funds: vec![],
+                }));
+            },
+            None => {
+                return Err(ContractError::NoStakedNft {  });
+            }
+        }
+    }
+
+    ACCOUNT_MAP.save(deps.storage, info.sender.clone(), &userinfo)?;
+    util::sync_voting_power(deps.storage, &env.block, &info.sender)?;
+    USER_STREAKS.remove(deps.storage, info.sender.clone());
+    USER_STREAK_PERIODS.remove(deps.storage, info.sender.clone());
+    TOTAL_STAKED.update(deps.storage, | exists| -> StdResult<_> {
+        Ok(exists.saturating_sub(token_ids.len() as u64))
+    })?;
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_submessages(hook_msgs)
+        .add_attribute("action", "emergency_withdraw")
+        .add_events(events)
+    )
+}
+
+/// Hands staked and unbonding NFTs back to their owners during a wind-down.
+/// Every call drains up to `limit` accounts, so repeating it until
+/// `remaining` is zero empties the contract.
+pub fn execute_emergency_return_all(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    limit: Option<u32>
+) -> Result<Response, ContractError> {
+    util::check_owner(deps.storage, info.sender.clone())?;
+
+    if !EMERGENCY.load(deps.storage)? {
+        return Err(ContractError::NotEmergency {  });
+    }
+
+    let limit = limit.unwrap_or(DEFAULT_RETURN_LIMIT).min(MAX_RETURN_LIMIT) as usize;
+
+    let accounts: Vec<(Addr, UserInfo)> = ACCOUNT_MAP
+        .range(deps.storage, None, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+    let claims: Vec<(Addr, Vec<NftClaim>)> = NFT_CLAIMS
+        .range(deps.storage, None, None, Order::Ascending)
+        .take(limit - accounts.len())
+        .collect::<StdResult<_>>()?;
+
+    let mut msgs: Vec<CosmosMsg> = vec![];
+    let mut hook_msgs: Vec<SubMsg> = vec![];
+    let mut events = vec![];
+    let mut returned = 0u64;
+    let mut unstaked = 0u64;
+    for (address, userinfo) in accounts.iter() {
+        for (index, nftinfo) in userinfo.staked_nfts.iter().enumerate() {
+            PENDING_TRANSFERS.remove(deps.storage, nftinfo.nft_id.clone());
+            // Receipt-backed positions go to whoever holds the receipt. The
+            // receipt cannot be burned without its holder, so it is voided.
+            let mut void_receipt = None;
+            let owner = if nftinfo.receipt {
+                let owner = util::get_receipt_owner(deps.storage, deps.querier, &nftinfo.nft_id).unwrap_or_else(|_| address.clone());
+                void_receipt = Some(util::receipt_id(deps.storage, &nftinfo.nft_id)?);
+                util::clear_receipt(deps.storage, &nftinfo.nft_id, true)?;
+                owner
+            } else {
+                address.clone()
+            };
+            util::release_rewards(deps.storage, nftinfo.airdrop)?;
+            events.push(util::nft_event("emergency_return", &owner, nftinfo)
+                .add_attribute("forfeited", nftinfo.airdrop)
+                .add_attributes(void_receipt.map(|id| ("void_receipt", id)))
+            );
+            // Hooks see the position leave the account that staked it.
+            let count = userinfo.staked_nfts.len() - index - 1;
+            hook_msgs.extend(util::emergency_hook_msgs(deps.storage, address, nftinfo, count)?);
+            msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: nftinfo.collection_address.to_string(),
+                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                    token_id: nftinfo.nft_id.clone(),
+                    recipient: owner.to_string()
+                })?,
+                funds: vec![],
+            }));
+            returned += 1;
+            unstaked += 1;
+        }
+        ACCOUNT_MAP.remove(deps.storage, address.clone());
+        util::sync_voting_power(deps.storage, &env.block, address)?;
+    }
+    TOTAL_STAKED.update(deps.storage, | exists| -> StdResult<_> {
+        Ok(exists.saturating_sub(unstaked))
+    })?;
+    for (address, nft_claims) in claims.iter() {
+        for claim in nft_claims.iter() {
+            events.push(util::event("withdraw_nft")
+                .add_attribute("user", address)
+                .add_attribute("collection", &claim.collection_address)
+                .add_attribute("token_id", claim.nft_id.clone())
+            );
+            msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: claim.collection_address.to_string(),
+                msg: to_binary(&Cw721ExecuteMsg::TransferNft {
+                    token_id: claim.nft_id.clone(),
+                    recipient: address.to_string()
+                })?,
+                funds: vec![],
+            }));
+            returned += 1;
+        }
+        NFT_CLAIMS.remove(deps.storage, address.clone());
+    }
+
+    let remaining = ACCOUNT_MAP.keys(deps.storage, None, None, Order::Ascending).count()
+        + NFT_CLAIMS.keys(deps.storage, None, None, Order::Ascending).count();
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_submessages(hook_msgs)
+        .add_attribute("action", "emergency_return_all")
+        .add_attribute("returned", returned.to_string())
+        .add_attribute("remaining", remaining.to_string())
+        .add_events(events)
+    )
+}
+
+pub fn execute_set_reward_recipient(
+    deps: DepsMut,
+    info: MessageInfo,
+    recipient: Option<Addr>
+) -> Result<Response, ContractError> {
+    match recipient.clone() {
+        Some(recipient) => {
+            let recipient = deps.api.addr_validate(recipient.as_str())?;
+            REWARD_RECIPIENT.save(deps.storage, info.sender.clone(), &recipient)?;
+        },
+        None => REWARD_RECIPIENT.remove(deps.storage, info.sender.clone()),
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "set_reward_recipient")
+        .add_event(util::event("set_reward_recipient")
+            .add_attribute("user", info.sender)
+            .add_attributes(recipient.map(|recipient| ("recipient", recipient)))
+        )
+    )
+}
+
+pub fn execute_approve_all(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    operator: Addr,
+    expires: Option<Expiration>
+) -> Result<Response, ContractError> {
+    let operator = deps.api.addr_validate(operator.as_str())?;
+    let expires = expires.unwrap_or_default();
+    if expires.is_expired(&env.block) {
+        return Err(ContractError::Expired {  });
+    }
+
+    OPERATORS.save(deps.storage, (info.sender.clone(), operator.clone()), &expires)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "approve_all")
+        .add_event(util::event("approve_all")
+            .add_attribute("user", info.sender)
+            .add_attribute("operator", operator)
+            .add_attribute("expires", expires.to_string())
+        )
+    )
+}
+
+pub fn execute_revoke_all(
+    deps: DepsMut,
+    info: MessageInfo,
+    operator: Addr
+) -> Result<Response, ContractError> {
+    OPERATORS.remove(deps.storage, (info.sender.clone(), operator.clone()));
+
+    Ok(Response::new()
+        .add_attribute("action", "revoke_all")
+        .add_event(util::event("revoke_all")
+            .add_attribute("user", info.sender)
+            .add_attribute("operator", operator)
+        )
+    )
+}
+
+pub fn execute_set_claim_mode(
+    deps: DepsMut,
+    info: MessageInfo,
+    mode: ClaimMode
+) -> Result<Response, ContractError> {
+    if mode == (ClaimMode::Vault {  }) && VAULT.load(deps.storage)?.is_none() {
+        return Err(ContractError::NoVault {  });
+    }
+    if let ClaimMode::Delegate { validator } = &mode {
+        if deps.querier.query_validator(validator)?.is_none() {
+            return Err(ContractError::InvalidValidator {  });
+        }
+    }
+
+    CLAIM_MODE.save(deps.storage, info.sender.clone(), &mode)?;
+
+    let (mode, validator) = match mode {
+        ClaimMode::Send {  } => ("send", None),
+        ClaimMode::Delegate { validator } => ("delegate", Some(validator)),
+        ClaimMode::Vault {  } => ("vault", None),
+    };
+
+    Ok(Response::new()
+        .add_attribute("action", "set_claim_mode")
+        .add_event(util::event("set_claim_mode")
+            .add_attribute("user", info.sender)
+            .add_attribute("mode", mode)
+            .add_attributes(validator.map(|validator| ("validator", validator)))
+        )
+    )
+}
+
+pub fn execute_undelegate(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    validator: String,
+    amount: Uint128
+) -> Result<Response, ContractError> {
+    let cfg = CONFIG.load(deps.storage)?;
+    let key = (info.sender.clone(), validator.clone());
+    let delegated = DELEGATIONS.may_load(deps.storage, key.clone())?.unwrap_or_default();
+
+    // `amount` is in shares of the contract's delegation to `validator`.
+    if amount.is_zero() || amount > delegated {
+        return Err(ContractError::InsufficientDelegation {  });
+    }
+
+    if delegated == amount {
+        DELEGATIONS.remove(deps.storage, key);
+    } else {
+        DELEGATIONS.save(deps.storage, key, &(delegated - amount))?;
+    }
+
+    // Shares are redeemed at the value of the delegation with its accrued
+    // rewards compounded: delegators share the staking yield, and the loss
+    // of a slash, in proportion.
+    let total_shares = VALIDATOR_DELEGATIONS.may_load(deps.storage, validator.clone())?.unwrap_or_default();
+    let (bonded, rewards) = util::query_delegation_value(deps.querier, &env.contract.address, &validator, &cfg.native_token)?;
+    let returned = amount.multiply_ratio(bonded + rewards, total_shares);
+    VALIDATOR_DELEGATIONS.save(deps.storage, validator.clone(), &total_shares.saturating_sub(amount))?;
+
+    let release_at = env.block.time.plus_seconds(UNDELEGATION_PERIOD.load(deps.storage)?);
+    let mut msgs = util::compound_msgs(&validator, rewards, &cfg.native_token);
+    if !returned.is_zero() {
+        UNDELEGATION_CLAIMS.create_claim(deps.storage, &info.sender, returned, Expiration::AtTime(release_at))?;
+        msgs.push(StakingMsg::Undelegate { validator: validator.clone(), amount: coin(returned.u128(), cfg.native_token.clone()) }.into());
+    }
+
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_attribute("action", "undelegate")
+        .add_event(util::event("undelegate")
+            .add_attribute("user", info.sender)
+            .add_attribute("validator", validator)
+            .add_attribute("shares", amount)
+            .add_attribute("amount", returned)
+            .add_attribute("denom", cfg.native_token)
+            .add_attribute("release_at", release_at.seconds().to_string())
+            .add_attributes((!rewards.is_zero()).then_some(("compounded", rewards)))
+        )
+    )
+}
+
+pub fn execute_claim_undelegated(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let cfg = CONFIG.load(deps.storage)?;
+    let amount = UNDELEGATION_CLAIMS.claim_tokens(deps.storage, &info.sender, &env.block, None)?;
+
+    if amount.is_zero() {
+        return Err(ContractError::NoReward {  });
+    }
+
+    // A slash during unbonding shrinks what comes back; the shortfall must
+    // not be paid out of rewards owed to stakers.
+    let reserved = util::get_reserved_rewards(deps.storage)?;
+    if util::get_token_amount(deps.querier, Denom::Native(cfg.native_token.clone()), env.contract.address.clone())? < amount + reserved {
+        return Err(ContractError::InsufficientCw20 {  });
+    }
+
+    Ok(Response::new()
+        .add_message(util::transfer_token_message(Denom::Native(cfg.native_token.clone()), amount, info.sender.clone())?)
+        .add_attribute("action", "claim_undelegated")
+        .add_event(util::event("claim_undelegated")
+            .add_attribute("user", info.sender)
+            .add_attribute("amount", amount)
+            .add_attribute("denom", cfg.native_token)
+        )
+    )
+}
+
+pub fn execute_add_hook(
+    deps: DepsMut,
+    info: MessageInfo,
+    addr: Addr
+) -> Result<Response, ContractError> {
+    util::check_owner(deps.storage, info.sender)?;
+
+    let addr = deps.api.addr_validate(addr.as_str())?;
+    HOOKS.add_hook(deps.storage, addr.clone())?;
+
+    Ok(Response::new()
+        .add_attribute("action", "add_hook")
+        .add_event(util::event("add_hook")
+            .add_attribute("hook", addr)
+        )
+    )
+}
+
+pub fn execute_remove_hook(
+    deps: DepsMut,
+    info: MessageInfo,
+    addr: Addr
+) -> Result<Response, ContractError> {
+    util::check_owner(deps.storage, info.sender)?;
+
+    HOOKS.remove_hook(deps.storage, addr.clone())?;
+
+    Ok(Response::new()
+        .add_attribute("action", "remove_hook")
+        .add_event(util::event("remove_hook")
+            .add_attribute("hook", addr)
+        )
+    )
+}
+
+pub fn execute_create_receipt_collection(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    code_id: u64,
+    name: String,
+    symbol: String
+) -> Result<Response, ContractError> {
+    util::check_owner(deps.storage, info.sender)?;
+
+    if RECEIPT_COLLECTION.load(deps.storage)?.is_some() {
+        return Err(ContractError::ReceiptCollectionExists {  });
+    }
+
+    let instantiate = WasmMsg::Instantiate {
+        admin: Some(env.contract.address.to_string()),
+        code_id,
+        msg: to_binary(&ReceiptInstantiateMsg {
+            name: name.clone(),
+            symbol,
+            minter: env.contract.address.to_string(),
+        })?,
+        funds: vec![],
+        label: name,
+    };
+
+    Ok(Response::new()
+        .add_submessage(SubMsg::reply_on_success(instantiate, util::RECEIPT_REPLY_ID))
+        .add_attribute("action", "create_receipt_collection")
+    )
+}
+
+pub fn execute_sync_receipt(
+    deps: DepsMut,
+    env: Env,
+    token_id: String
+) -> Result<Response, ContractError> {
+    let (synced, sync_hooks) = util::sync_receipt(deps.storage, deps.querier, &env.block, &token_id)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "sync_receipt")
+        .add_submessages(sync_hooks)
+        .add_events(synced)
+    )
+}
+
+pub fn execute_sync_voting_power(
+    deps: DepsMut,
+    env: Env,
+    addresses: Vec<Addr>
+) -> Result<Response, ContractError> {
+    let mut events = vec![];
+    for address in addresses.iter() {
+        let address = deps.api.addr_validate(address.as_str())?;
+        util::sync_voting_power(deps.storage, &env.block, &address)?;
+        events.push(util::event("sync_voting_power")
+            .add_attribute("user", &address)
+            .add_attribute("power", VOTING_POWER.may_load(deps.storage, address.clone())?.unwrap_or_default())
+        );
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "sync_voting_power")
+        .add_events(events)
+    )
+}
+
+pub fn execute_transfer_stake(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_id: String,
+    to: Addr,
+    require_acceptance: Option<bool>
+) -> Result<Response, ContractError> {
+    // A move unstakes for the sender and stakes for `to`.
+    util::check_enabled(deps.storage, util::Operation::Unstake)?;
+    util::check_enabled(deps.storage, util::Operation::Stake)?;
+    util::check_not_emergency(deps.storage)?;
+
+    let to = deps.api.addr_validate(to.as_str())?;
+    if to == info.sender {
+        return Err(ContractError::InvalidTransfer {  });
+    }
+    util::check_can_receive(deps.storage, &to, &token_id)?;
+
+    let userinfo = ACCOUNT_MAP.load(deps.storage, info.sender.clone())?;
+    let nftinfo = userinfo.staked_nfts.iter()
+        .find(|nft| nft.nft_id == token_id)
+        .ok_or(ContractError::NoStakedNft {  })?;
+
+    // The receipt decides who owns these; it is what gets transferred.
+    if nftinfo.receipt {
+        return Err(ContractError::ReceiptBacked {  });
+    }
+
+    if require_acceptance.unwrap_or(false) {
+        PENDING_TRANSFERS.save(deps.storage, token_id, &PendingTransfer {
+            from: info.sender.clone(),
+            to: to.clone(),
+        })?;
+
+        return Ok(Response::new()
+            .add_attribute("action", "transfer_stake")
+            .add_event(util::nft_event("transfer_stake_pending", &info.sender, nftinfo)
+                .add_attribute("to", to)
+            )
+        );
+    }
+
+    PENDING_TRANSFERS.remove(deps.storage, token_id.clone());
+    let (event, hook_msgs) = util::move_position(deps.storage, &env.block, &info.sender, &to, &token_id)?;
+
+    Ok(Response::new()
+        .add_submessages(hook_msgs)
+        .add_attribute("action", "transfer_stake")
+        .add_event(event)
+    )
+}
+
+pub fn execute_accept_stake(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token_id: String
+) -> Result<Response, ContractError> {
+    util::check_enabled(deps.storage, util::Operation::Unstake)?;
+    util::check_enabled(deps.storage, util::Operation::Stake)?;
+    util::check_not_emergency(deps.storage)?;
+
+    let transfer = PENDING_TRANSFERS.may_load(deps.storage, token_id.clone())?
+        .ok_or(ContractError::NoPendingTransfer {  })?;
+    if transfer.to != info.sender {
+        return Err(ContractError::Unauthorized {  });
+    }
+    // The denylist or caps may have changed since the transfer was offered.
+    util::check_can_receive(deps.storage, &transfer.to, &token_id)?;
+
+    PENDING_TRANSFERS.remove(deps.storage, token_id.clone());
+    let (event, hook_msgs) = util::move_position(deps.storage, &env.block, &transfer.from, &transfer.to, &token_id)?;
+
+    Ok(Response::new()
+        .add_submessages(hook_msgs)
+        .add_attribute("action", "accept_stake")
+        .add_event(event)
+    )
+}
+
+pub fn execute_cancel_stake_transfer(
+    deps: DepsMut,
+    info: MessageInfo,
+    token_id: String
+) -> Result<Response, ContractError> {
+    let transfer = PENDING_TRANSFERS.may_load(deps.storage, token_id.clone())?
+        .ok_or(ContractError::NoPendingTransfer {  })?;
+    if info.sender != transfer.from && info.sender != transfer.to {
+        return Err(ContractError::Unauthorized {  });
+    }
+
+    PENDING_TRANSFERS.remove(deps.storage, token_id.clone());
+
+    Ok(Response::new()
+        .add_attribute("action", "cancel_stake_transfer")
+        .add_event(util::event("transfer_stake_cancelled")
+            .add_attribute("user", info.sender)
+            .add_attribute("token_id", token_id)
+            .add_attribute("from", transfer.from)
+            .add_attribute("to", transfer.to)
+        )
+    )
+}
+
+pub fn execute_claim(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    claim_nft_id: String,
+    recipient: Option<Addr>,
+    owner: Option<Addr>
+) -> Result<Response, ContractError> {
+    util::check_enabled(deps.storage, util::Operation::Claim)?;
+    util::check_not_emergency(deps.storage)?;
+
+    let staker = util::resolve_staker(deps.storage, &env.block, info.sender.clone(), owner)?;
+    // Operators claim on the staker's terms and cannot pick the payee.
+    let recipient = match recipient {
+        Some(_) if staker != info.sender => return Err(ContractError::Unauthorized {  }),
+        Some(recipient) => deps.api.addr_validate(recipient.as_str())?,
+        None => REWARD_RECIPIENT.may_load(deps.storage, staker.clone())?.unwrap_or_else(|| staker.clone()),
+    };
+
+    let (synced, sync_hooks) = util::sync_receipt(deps.storage, deps.querier, &env.block, &claim_nft_id)?;
+    let cfg = CONFIG.load(deps.storage)?;
+    let mut userinfo = ACCOUNT_MAP.load(deps.storage, staker.clone())?;
+
     if userinfo.staked_nfts.is_empty() {
         return Err(ContractError::NoStakedNft {  });
     }
@@ -477,36 +1921,131 @@ pub fn execute_claim(
                 return Err(crate::ContractError::InsufficientCw20 {  });
             }
     
-            let reward_msg = util::transfer_token_message(Denom::Native(cfg.native_token.clone()), nftinfo.airdrop, info.sender.clone())?;
+            let reward = coin(nftinfo.airdrop.u128(), cfg.native_token.clone());
+            let mode = CLAIM_MODE.may_load(deps.storage, staker.clone())?.unwrap_or(ClaimMode::Send {  });
+            let mode_name = match mode {
+                ClaimMode::Send {  } => "send",
+                ClaimMode::Delegate { .. } => "delegate",
+                ClaimMode::Vault {  } => "vault",
+            };
+            let reward_msgs: Vec<CosmosMsg> = match mode {
+                ClaimMode::Send {  } => vec![util::transfer_token_message(Denom::Native(cfg.native_token.clone()), nftinfo.airdrop, recipient.clone())?],
+                ClaimMode::Delegate { validator } => {
+                    // The reward buys shares at the value the delegation has
+                    // once its accrued rewards are compounded, so earlier
+                    // delegators keep what they earned.
+                    let (bonded, rewards) = util::query_delegation_value(deps.querier, &env.contract.address, &validator, &cfg.native_token)?;
+                    let total_shares = VALIDATOR_DELEGATIONS.may_load(deps.storage, validator.clone())?.unwrap_or_default();
+                    let shares = util::get_delegation_shares(nftinfo.airdrop, total_shares, bonded + rewards)?;
+                    DELEGATIONS.update(deps.storage, (staker.clone(), validator.clone()), |exists| -> StdResult<_> {
+                        Ok(exists.unwrap_or_default() + shares)
+                    })?;
+                    VALIDATOR_DELEGATIONS.save(deps.storage, validator.clone(), &(total_shares + shares))?;
+                    let mut msgs = util::compound_msgs(&validator, rewards, &cfg.native_token);
+                    msgs.push(StakingMsg::Delegate { validator, amount: reward }.into());
+                    msgs
+                },
+                ClaimMode::Vault {  } => {
+                    let vault = VAULT.load(deps.storage)?.ok_or(ContractError::NoVault {  })?;
+                    vec![WasmMsg::Execute {
+                        contract_addr: vault.to_string(),
+                        msg: to_binary(&VaultExecuteMsg::Deposit { recipient: recipient.to_string() })?,
+                        funds: vec![reward],
+                    }.into()]
+                },
+            };
     
             let amount = nftinfo.airdrop;
             nftinfo.airdrop = Uint128::zero();
+            util::release_rewards(deps.storage, amount)?;
             userinfo.total_earnd += amount;
+            let hook_msgs = util::stake_hook_msgs(deps.storage, StakeChangeKind::Claim, &staker, &nftinfo, userinfo.staked_nfts.len())?;
+            let event = util::nft_event("claim", &staker, &nftinfo)
+                .add_attribute("sender", &info.sender)
+                .add_attribute("recipient", &recipient)
+                .add_attribute("amount", amount)
+                .add_attribute("denom", cfg.native_token)
+                .add_attribute("mode", mode_name);
             userinfo.staked_nfts[index] = nftinfo;
     
-            ACCOUNT_MAP.save(deps.storage, info.sender.clone(), &userinfo)?;
+            ACCOUNT_MAP.save(deps.storage, staker.clone(), &userinfo)?;
+            util::sync_voting_power(deps.storage, &env.block, &staker)?;
                 
-            return Ok(Response::new()
-                .add_message(reward_msg)
+            Ok(Response::new()
+                .add_messages(reward_msgs)
+                .add_submessages(hook_msgs)
                 .add_attribute("action", "claim")
-                .add_attribute("address", info.sender.clone().to_string())
-                .add_attribute("claimed_amount", amount)
-            );
+                .add_submessages(sync_hooks)
+                .add_events(synced)
+                .add_event(event)
+            )
         },
         None => {
-            return Err(ContractError::NoStakedNft {  });
+            Err(ContractError::NoStakedNft {  })
         }
     }
 }
 
+/// Only non-fatal hooks ask for a reply, and only when they fail. The error
+/// is recorded and swallowed so the staking action still goes through. The
+/// receipt collection replies once instantiated to record its address.
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
+pub fn reply(
+    deps: DepsMut,
+    _env: Env,
+    msg: Reply
+) -> Result<Response, ContractError> {
+    match (msg.id, msg.result.clone()) {
+        (util::HOOK_REPLY_ID, SubMsgResult::Err(error)) => Ok(Response::new()
+            .add_attribute("action", "hook_failed")
+            .add_event(util::event("hook_failed")
+                .add_attribute("error", error)
+            )
+        ),
+        (util::RECEIPT_REPLY_ID, SubMsgResult::Ok(_)) => {
+            let res = parse_reply_instantiate_data(msg)?;
+            let collection = deps.api.addr_validate(&res.contract_address)?;
+            RECEIPT_COLLECTION.save(deps.storage, &Some(collection.clone()))?;
+
+            Ok(Response::new()
+                .add_attribute("action", "create_receipt_collection")
+                .add_event(util::event("create_receipt_collection")
+                    .add_attribute("collection", collection)
+                )
+            )
+        },
+        _ => Err(ContractError::Std(StdError::generic_err("unknown reply"))),
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> Result<Binary, ContractError> {
+    let res = match msg {
         QueryMsg::GetConfig {} => to_binary(&query_config(deps, env)?),
         QueryMsg::GetTotalEarned { address } => to_binary(&query_total_earned(deps, address)?),
         QueryMsg::GetTotalLocked {} => to_binary(&query_total_locked(deps, env)?),
-        QueryMsg::StakedNfts { address } => to_binary(&query_staked_nfts(deps, address)?)
-    }
+        QueryMsg::StakedNfts { address } => to_binary(&query_staked_nfts(deps, env, address)?),
+        QueryMsg::LockTiers {} => to_binary(&query_lock_tiers(deps)?),
+        QueryMsg::RewardRecipient { address } => to_binary(&query_reward_recipient(deps, address)?),
+        QueryMsg::Operator { owner, operator } => to_binary(&query_operator(deps, env, owner, operator)?),
+        QueryMsg::Delegations { address } => to_binary(&query_delegations(deps, env, address)?),
+        QueryMsg::Hooks {} => to_binary(&query_hooks(deps)?),
+        QueryMsg::StakingAgents {} => to_binary(&query_staking_agents(deps)?),
+        QueryMsg::PendingTransfer { token_id } => to_binary(&PendingTransferResponse {
+            transfer: PENDING_TRANSFERS.may_load(deps.storage, token_id)?,
+        }),
+        QueryMsg::VotingPowerAtHeight { address, height } => to_binary(&query_voting_power_at_height(deps, env, address, height)?),
+        QueryMsg::TotalPowerAtHeight { height } => to_binary(&query_total_power_at_height(deps, env, height)?),
+        QueryMsg::Denylist {} => to_binary(&query_denylist(deps)?),
+        QueryMsg::Streak { address } => to_binary(&query_streak(deps, env, address)?),
+        QueryMsg::StreakTiers {} => to_binary(&query_streak_tiers(deps)?),
+        QueryMsg::PendingChanges {} => to_binary(&query_pending_changes(deps)?),
+        QueryMsg::Info {} => to_binary(&InfoResponse { info: get_contract_version(deps.storage)? }),
+        QueryMsg::Sponsored { address } => to_binary(&query_sponsored(deps, address)?),
+        QueryMsg::PauseFlags {} => to_binary(&query_pause_flags(deps)?),
+        QueryMsg::NftClaims { address } => to_binary(&query_nft_claims(deps, address)?)
+    };
+    Ok(res?)
 }
 
 pub fn query_config(deps: Deps, env: Env) -> StdResult<ConfigResponse> {
@@ -522,61 +2061,250 @@ pub fn query_config(deps: Deps, env: Env) -> StdResult<ConfigResponse> {
         fee_address: config.fee_address,
         duration: config.duration,
         enabled: config.enabled,
+        emergency: EMERGENCY.load(deps.storage)?,
         current_time: env.block.time.seconds(),
         current_airdrop_time: current_airdrop.time.seconds(),
-        start_airdrop: start_airdrop.clone(),
-        total_airdrop: total_airdrop.clone(),
-        total_staked: total_staked.clone(),
-        locktime_fee: locktime_fee.clone()
+        start_airdrop,
+        total_airdrop,
+        airdrop_dust: AIRDROP_DUST.load(deps.storage)?,
+        unclaimed_rewards: UNCLAIMED_REWARDS.load(deps.storage)?,
+        total_staked,
+        locktime_fee,
+        receipt_collection: RECEIPT_COLLECTION.load(deps.storage)?,
+        max_per_user: config.max_per_user,
+        max_total_staked: config.max_total_staked,
+        weighting: config.weighting,
+        min_stake_age: config.min_stake_age,
+        time_weighted: config.time_weighted
     })
 }
 
 pub fn query_total_earned(deps: Deps, address: Addr) -> StdResult<TotalEarnedResponse> {
-    let userinfo = ACCOUNT_MAP.load(deps.storage, address);
+    let address = deps.api.addr_validate(address.as_str())?;
+    let total_earned = ACCOUNT_MAP.may_load(deps.storage, address)?
+        .map(|userinfo| userinfo.total_earnd)
+        .unwrap_or_default();
 
-    match userinfo {
-        Ok(userinfo) => {
-            Ok(TotalEarnedResponse {
-                total_earned: userinfo.total_earnd.clone()
-            })
-        },
-        Err(_error) => {
-            Ok(TotalEarnedResponse {
-                total_earned: Uint128::zero()
-            })
-        }
-    }
+    Ok(TotalEarnedResponse {
+        total_earned
+    })
 }
 
-pub fn query_total_locked(deps: Deps, env: Env) -> StdResult<TotalLockedResponse> {
+pub fn query_total_locked(deps: Deps, env: Env) -> Result<TotalLockedResponse, ContractError> {
     let config: Config = CONFIG.load(deps.storage)?;
-    let nft_count = util::get_in_locktime_nft_count(deps.storage, env.block.clone(), config.collection_address.clone());
-    match nft_count {
-        Ok(nft_count) => {
-            Ok(TotalLockedResponse {
-                count: nft_count.clone()
-            })
-        },
-        Err(_error) => {
-            Ok(TotalLockedResponse {
-                count: Uint128::zero()
-            })
-        }
+    let nft_count = util::get_in_locktime_nft_count(deps.storage, env.block, config.collection_address)?;
+
+    Ok(TotalLockedResponse {
+        count: nft_count
+    })
+}
+
+pub fn query_lock_tiers(deps: Deps) -> StdResult<LockTiersResponse> {
+    Ok(LockTiersResponse {
+        tiers: LOCK_TIERS.load(deps.storage)?,
+        max_lock_duration: MAX_LOCK_DURATION.load(deps.storage)?,
+    })
+}
+
+pub fn query_reward_recipient(deps: Deps, address: Addr) -> StdResult<RewardRecipientResponse> {
+    let address = deps.api.addr_validate(address.as_str())?;
+    Ok(RewardRecipientResponse {
+        recipient: REWARD_RECIPIENT.may_load(deps.storage, address.clone())?.unwrap_or_else(|| address.clone()),
+        address,
+    })
+}
+
+pub fn query_operator(deps: Deps, env: Env, owner: Addr, operator: Addr) -> StdResult<OperatorResponse> {
+    let owner = deps.api.addr_validate(owner.as_str())?;
+    let operator = deps.api.addr_validate(operator.as_str())?;
+    let expires = OPERATORS.may_load(deps.storage, (owner, operator))?
+        .filter(|expires| !expires.is_expired(&env.block));
+    Ok(OperatorResponse {
+        approved: expires.is_some(),
+        expires,
+    })
+}
+
+pub fn query_delegations(deps: Deps, env: Env, address: Addr) -> StdResult<DelegationsResponse> {
+    let address = deps.api.addr_validate(address.as_str())?;
+    let cfg = CONFIG.load(deps.storage)?;
+    let shares = DELEGATIONS
+        .prefix(address.clone())
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    let mut delegations = vec![];
+    for (validator, shares) in shares {
+        let total_shares = VALIDATOR_DELEGATIONS.load(deps.storage, validator.clone())?;
+        let (bonded, rewards) = util::query_delegation_value(deps.querier, &env.contract.address, &validator, &cfg.native_token)?;
+        delegations.push(DelegationResponse {
+            amount: shares.multiply_ratio(bonded + rewards, total_shares),
+            validator,
+            shares,
+        });
     }
+    Ok(DelegationsResponse {
+        claim_mode: CLAIM_MODE.may_load(deps.storage, address.clone())?.unwrap_or(ClaimMode::Send {  }),
+        delegations,
+        undelegations: UNDELEGATION_CLAIMS.query_claims(deps, &address)?,
+    })
+}
+
+pub fn query_voting_power_at_height(
+    deps: Deps,
+    env: Env,
+    address: String,
+    height: Option<u64>
+) -> StdResult<VotingPowerAtHeightResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let power = match height {
+        Some(height) => VOTING_POWER.may_load_at_height(deps.storage, address, height)?,
+        None => VOTING_POWER.may_load(deps.storage, address)?,
+    };
+
+    Ok(VotingPowerAtHeightResponse {
+        power: power.unwrap_or_default(),
+        height: height.unwrap_or(env.block.height),
+    })
+}
+
+pub fn query_total_power_at_height(
+    deps: Deps,
+    env: Env,
+    height: Option<u64>
+) -> StdResult<TotalPowerAtHeightResponse> {
+    let power = match height {
+        Some(height) => TOTAL_POWER.may_load_at_height(deps.storage, height)?,
+        None => TOTAL_POWER.may_load(deps.storage)?,
+    };
+
+    Ok(TotalPowerAtHeightResponse {
+        power: power.unwrap_or_default(),
+        height: height.unwrap_or(env.block.height),
+    })
+}
+
+pub fn query_staking_agents(deps: Deps) -> StdResult<StakingAgentsResponse> {
+    Ok(StakingAgentsResponse {
+        agents: STAKING_AGENTS
+            .keys(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()?,
+    })
+}
+
+pub fn query_streak(deps: Deps, env: Env, address: String) -> StdResult<StreakResponse> {
+    let address = deps.api.addr_validate(&address)?;
+    let cfg = CONFIG.load(deps.storage)?;
+    let tiers = STREAK_TIERS.load(deps.storage)?;
+    let renewal_window = RENEWAL_WINDOW.load(deps.storage)?;
+    let staked_nfts = ACCOUNT_MAP.may_load(deps.storage, address.clone())?
+        .map(|userinfo| userinfo.staked_nfts)
+        .unwrap_or_default();
+    let nfts = staked_nfts.iter()
+        .map(|nftinfo| {
+            let streak = util::get_streak(nftinfo, cfg.duration, renewal_window, env.block.time.seconds());
+            NftStreakResponse {
+                token_id: nftinfo.nft_id.clone(),
+                streak,
+                multiplier: util::get_streak_multiplier(&tiers, streak),
+            }
+        })
+        .collect();
+    let stored = USER_STREAKS.may_load(deps.storage, address.clone())?.unwrap_or_default();
+    Ok(StreakResponse {
+        streak: util::get_user_streak(stored, &staked_nfts, renewal_window, env.block.time.seconds()),
+        address,
+        nfts,
+    })
+}
+
+pub fn query_streak_tiers(deps: Deps) -> StdResult<StreakTiersResponse> {
+    Ok(StreakTiersResponse {
+        tiers: STREAK_TIERS.load(deps.storage)?,
+        renewal_window: RENEWAL_WINDOW.load(deps.storage)?,
+    })
+}
+
+pub fn query_pending_changes(deps: Deps) -> StdResult<PendingChangesResponse> {
+    let changes = PENDING_CHANGES
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(id, pending)| PendingChangeResponse {
+            id,
+            change: pending.change,
+            eta: pending.eta,
+        }))
+        .collect::<StdResult<_>>()?;
+    Ok(PendingChangesResponse {
+        changes,
+        delay: TIMELOCK_DELAY.load(deps.storage)?,
+    })
+}
+
+pub fn query_denylist(deps: Deps) -> StdResult<DenylistResponse> {
+    Ok(DenylistResponse {
+        mode: DENYLIST_MODE.load(deps.storage)?,
+        tokens: DENIED_TOKENS
+            .keys(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()?,
+        addresses: DENIED_ADDRESSES
+            .keys(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()?,
+    })
+}
+
+pub fn query_hooks(deps: Deps) -> StdResult<HooksResponse> {
+    Ok(HooksResponse {
+        hooks: HOOKS.query_hooks(deps)?.hooks,
+        fatal: HOOK_ERRORS_FATAL.load(deps.storage)?,
+    })
+}
+
+pub fn query_sponsored(deps: Deps, address: Addr) -> StdResult<SponsoredResponse> {
+    let address = deps.api.addr_validate(address.as_str())?;
+    Ok(SponsoredResponse {
+        amount: AIRDROP_SPONSORS.may_load(deps.storage, address.clone())?.unwrap_or_default(),
+        address,
+    })
+}
+
+pub fn query_pause_flags(deps: Deps) -> StdResult<PauseFlagsResponse> {
+    Ok(PauseFlagsResponse {
+        enabled: CONFIG.load(deps.storage)?.enabled,
+        pauser: PAUSER.load(deps.storage)?,
+        flags: PAUSE_FLAGS.load(deps.storage)?,
+    })
+}
+
+pub fn query_nft_claims(deps: Deps, address: Addr) -> StdResult<NftClaimsResponse> {
+    let address = deps.api.addr_validate(address.as_str())?;
+    Ok(NftClaimsResponse {
+        unbonding_period: UNBONDING_PERIOD.load(deps.storage)?,
+        claims: NFT_CLAIMS.may_load(deps.storage, address)?.unwrap_or_default(),
+    })
 }
 
 pub fn query_staked_nfts(
     deps: Deps, 
+    env: Env,
     address: Addr
 ) -> StdResult<StakedNftsResponse> {
-    let userinfo: UserInfo = ACCOUNT_MAP.load(deps.storage, address.clone()).expect("Failed to load nfts");
+    let address = deps.api.addr_validate(address.as_str())?;
+    let config: Config = CONFIG.load(deps.storage)?;
+    let locktime_fee = LOCKTIME_FEE.load(deps.storage)?;
+    let staked_nfts = ACCOUNT_MAP.may_load(deps.storage, address.clone())?
+        .map(|userinfo| userinfo.staked_nfts)
+        .unwrap_or_default();
     let mut address_maps : Vec<StakedNftResponse> = Vec::new();
-    for nft in userinfo.staked_nfts {
+    for nft in staked_nfts {
         address_maps.push(StakedNftResponse { 
             account_address: address.clone(), 
+            lock_time: util::get_lock_time(&nft, config.duration, env.block.time.seconds()),
             nft_id: nft.nft_id, 
             airdrop: nft.airdrop, 
-            lock_time: nft.lock_time,
+            auto_renew: nft.auto_renew,
+            receipt: nft.receipt,
+            staked_at: nft.staked_at,
+            duration: nft.duration.unwrap_or(config.duration),
+            locktime_fee: nft.locktime_fee.unwrap_or(locktime_fee),
         })
     }
     let resp = StakedNftsResponse { nft_maps: address_maps };
@@ -1,17 +1,42 @@
 use cosmwasm_std::{StdError, Uint128};
+use cw_controllers::HookError;
+use cw_utils::{ParseReplyError, PaymentError};
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, PartialEq)]
 pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
 
+    #[error("{0}")]
+    Payment(#[from] PaymentError),
+
+    #[error("{0}")]
+    ParseReply(#[from] ParseReplyError),
+
+    #[error("{0}")]
+    Hook(#[from] HookError),
+
     #[error("Unauthorized")]
     Unauthorized {},
 
+    #[error("Expired")]
+    Expired {},
+
     #[error("Disabled")]
     Disabled {},
 
+    #[error("Paused: {operation}")]
+    Paused {
+        operation: String
+    },
+
+    #[error("Emergency Mode")]
+    Emergency {},
+
+    #[error("Not In Emergency Mode")]
+    NotEmergency {},
+
     #[error("Airdrop Not Started")]
     NotStarted {},
 
@@ -55,4 +80,77 @@ pub enum ContractError {
 
     #[error("No Airdrop Nfts")]
     NoAirdropNft {},
+
+    #[error("Invalid Lock Tier")]
+    InvalidTier {},
+
+    #[error("Invalid Lock Extension")]
+    InvalidExtension {},
+
+    #[error("No Matured Nft Claims")]
+    NoMaturedClaims {},
+
+    #[error("No Vault Configured")]
+    NoVault {},
+
+    #[error("Insufficient Delegation")]
+    InsufficientDelegation {},
+
+    #[error("InvalidValidator")]
+    InvalidValidator {},
+
+    #[error("OverMaxLock")]
+    OverMaxLock {
+        max_lock_duration: u64
+    },
+
+    #[error("NoReceiptCollection")]
+    NoReceiptCollection {},
+
+    #[error("ReceiptCollectionExists")]
+    ReceiptCollectionExists {},
+
+    #[error("ReceiptBacked")]
+    ReceiptBacked {},
+
+    #[error("InvalidTransfer")]
+    InvalidTransfer {},
+
+    #[error("NoPendingTransfer")]
+    NoPendingTransfer {},
+
+    #[error("DeniedToken")]
+    DeniedToken {},
+
+    #[error("DeniedAddress")]
+    DeniedAddress {},
+
+    #[error("MaxPerUserExceeded: an account may stake at most {max_per_user} NFTs")]
+    MaxPerUserExceeded {
+        max_per_user: u64
+    },
+
+    #[error("MaxTotalStakedExceeded: the contract accepts at most {max_total_staked} staked NFTs")]
+    MaxTotalStakedExceeded {
+        max_total_staked: u64
+    },
+
+    #[error("InvalidWeighting")]
+    InvalidWeighting {},
+
+    #[error("InvalidStreakTier")]
+    InvalidStreakTier {},
+
+    #[error("NoPendingChange")]
+    NoPendingChange {},
+
+    #[error("TimelockNotExpired: the change can be executed from {eta}")]
+    TimelockNotExpired {
+        eta: u64
+    },
+
+    #[error("InvalidUnbondingPeriod: the unbonding period is at most {max_unbonding_period} seconds")]
+    InvalidUnbondingPeriod {
+        max_unbonding_period: u64
+    },
 }
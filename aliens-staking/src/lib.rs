@@ -4,4 +4,7 @@ pub mod msg;
 pub mod state;
 pub mod util;
 
+#[cfg(test)]
+mod test;
+
 pub use crate::error::ContractError;
@@ -1,6 +1,10 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Uint128, Addr};
+use cosmwasm_std::{Uint128, Addr, Decimal, Empty};
 use cw721::Cw721ReceiveMsg;
+use cw_utils::Expiration;
+use cw_controllers::ClaimsResponse;
+use cw2::ContractVersion;
+use crate::state::{ClaimMode, ConfigChange, DenylistMode, LockTier, NftClaim, PauseFlags, PendingTransfer, StreakTier, Weighting};
 
 #[cw_serde]
 pub struct InstantiateMsg {
@@ -13,9 +17,14 @@ pub struct InstantiateMsg {
 
 #[cw_serde]
 pub enum ExecuteMsg {
+    /// Queued behind the timelock; apply it with `ExecuteChange`.
     UpdateOwner {
         owner: Addr,
     },
+    /// Queued behind the timelock, like `UpdateOwner`, `UpdateDuration`,
+    /// `UpdateConfig`, `UpdateTimelockDelay`, `UpdateUnbondingPeriod`,
+    /// `UpdateLockTiers`, `UpdateStakingLimits` and `UpdateVault`; apply it
+    /// with `ExecuteChange`.
     UpdateFeeAddress {
         fee_address: Addr,
     },
@@ -33,12 +42,150 @@ pub enum ExecuteMsg {
         amount: Uint128
     },
     Airdrop { airdrop_amount: Uint128 },
+    /// Distributes the `native_token` funds sent with the message. Open to
+    /// anyone, so partners can sponsor an airdrop without owner rights.
+    FundAirdrop {},
     AirdropRestart { },
     ReceiveNft (Cw721ReceiveMsg),
-    Restake { restake_nft_id: String },
+    /// `owner` lets an approved operator act for the staker.
+    Restake { restake_nft_id: String, owner: Option<Addr> },
     Unstake { unstake_nft_id: String },
-    Claim { claim_nft_id: String },
+    /// Pays `recipient`, else the staker's reward recipient, else the staker.
+    /// Operators acting for `owner` cannot choose the recipient.
+    Claim { claim_nft_id: String, recipient: Option<Addr>, owner: Option<Addr> },
     UpdateDuration { duration: u64 },
+    SetAutoRenew { token_ids: Vec<String>, enabled: bool },
+    UpdateLockTiers {
+        tiers: Vec<LockTier>,
+        max_lock_duration: u64,
+    },
+    ExtendLock {
+        token_id: String,
+        additional_seconds: Option<u64>,
+        tier: Option<u32>,
+        owner: Option<Addr>,
+    },
+    UpdateUnbondingPeriod { unbonding_period: u64 },
+    WithdrawNft {},
+    SetEmergency { enabled: bool },
+    EmergencyWithdraw { token_ids: Option<Vec<String>> },
+    EmergencyReturnAll { limit: Option<u32> },
+    UpdatePauser { pauser: Option<Addr> },
+    UpdatePauseFlags { flags: PauseFlags },
+    SetRewardRecipient { recipient: Option<Addr> },
+    /// Lets `operator` claim, restake and extend locks for the sender. It
+    /// can never unstake.
+    ApproveAll { operator: Addr, expires: Option<Expiration> },
+    RevokeAll { operator: Addr },
+    SetClaimMode { mode: ClaimMode },
+    /// Queued behind the timelock: vault-mode claims are sent to it.
+    UpdateVault { vault: Option<Addr> },
+    UpdateUndelegationPeriod { undelegation_period: u64 },
+    /// Unbonds `amount` of the sender's shares of the delegation to
+    /// `validator`. Its staking rewards are compounded into the shares and
+    /// slashes shared by everyone delegated through it.
+    Undelegate { validator: String, amount: Uint128 },
+    ClaimUndelegated {},
+    AddHook { addr: Addr },
+    RemoveHook { addr: Addr },
+    /// When false, a failing hook is logged and skipped instead of
+    /// reverting the staking action.
+    UpdateHookErrorsFatal { fatal: bool },
+    /// Owner-managed allowlist of senders that may stake for a
+    /// `beneficiary`.
+    UpdateStakingAgents { add: Vec<Addr>, remove: Vec<Addr> },
+    /// Instantiates the receipt collection from a cw721-base `code_id`, with
+    /// this contract as minter. Can only be done once.
+    CreateReceiptCollection { code_id: u64, name: String, symbol: String },
+    /// Moves a receipt-backed position to the current receipt holder.
+    SyncReceipt { token_id: String },
+    /// Recomputes the voting power of `addresses`, dropping that of locks
+    /// which have run out. Anyone may call it.
+    SyncVotingPower { addresses: Vec<Addr> },
+    /// Moves a staked position, with its pending reward and lock, to `to`.
+    /// With `require_acceptance` the move waits for `AcceptStake` from `to`.
+    /// `to` must be allowed to stake the token and stay within `max_per_user`.
+    TransferStake { token_id: String, to: Addr, require_acceptance: Option<bool> },
+    AcceptStake { token_id: String },
+    /// Withdraws a pending transfer; either side may cancel.
+    CancelStakeTransfer { token_id: String },
+    UpdateDenylist {
+        add_tokens: Vec<String>,
+        remove_tokens: Vec<String>,
+        add_addresses: Vec<Addr>,
+        remove_addresses: Vec<Addr>,
+    },
+    UpdateDenylistMode { mode: DenylistMode },
+    /// Sets the staking caps (`None` lifts a cap) and the airdrop weighting.
+    UpdateStakingLimits {
+        max_per_user: Option<u64>,
+        max_total_staked: Option<u64>,
+        weighting: Weighting,
+    },
+    UpdateAirdropEligibility {
+        min_stake_age: u64,
+        time_weighted: bool,
+    },
+    UpdateStreakTiers {
+        tiers: Vec<StreakTier>,
+        renewal_window: u64,
+    },
+    UpdateTimelockDelay { delay: u64 },
+    /// Applies a queued change once its timelock has passed.
+    ExecuteChange { id: u64 },
+    CancelChange { id: u64 },
+}
+
+#[cw_serde]
+pub enum StakeChangeKind {
+    Stake,
+    Unstake,
+    Restake,
+    Claim,
+}
+
+/// Sent to every registered hook after a stake changes. `count` is the
+/// number of NFTs `user` has staked afterwards.
+#[cw_serde]
+pub struct StakeChangedHookMsg {
+    pub kind: StakeChangeKind,
+    pub user: Addr,
+    pub collection: Addr,
+    pub token_id: String,
+    pub count: u64,
+}
+
+/// Hook contracts implement this variant in their own `ExecuteMsg`.
+#[cw_serde]
+pub enum StakeChangedExecuteMsg {
+    StakeChangeHook(StakeChangedHookMsg),
+}
+
+/// Subset of the cw721-base messages used to run the receipt collection.
+#[cw_serde]
+pub struct ReceiptInstantiateMsg {
+    pub name: String,
+    pub symbol: String,
+    pub minter: String,
+}
+
+#[cw_serde]
+pub enum ReceiptExecuteMsg {
+    Mint {
+        token_id: String,
+        owner: String,
+        token_uri: Option<String>,
+        extension: Empty,
+    },
+    Burn {
+        token_id: String,
+    },
+}
+
+/// Message sent to the vault contract with the compounded rewards attached.
+#[cw_serde]
+pub enum VaultExecuteMsg {
+    Deposit { recipient: String },
 }
 
 #[cw_serde]
@@ -61,6 +208,87 @@ pub enum QueryMsg {
     StakedNfts {
         address: Addr
     },
+
+    #[returns(LockTiersResponse)]
+    LockTiers {
+    },
+
+    #[returns(RewardRecipientResponse)]
+    RewardRecipient {
+        address: Addr
+    },
+
+    #[returns(OperatorResponse)]
+    Operator {
+        owner: Addr,
+        operator: Addr
+    },
+
+    #[returns(DelegationsResponse)]
+    Delegations {
+        address: Addr
+    },
+
+    #[returns(HooksResponse)]
+    Hooks {
+    },
+
+    #[returns(StakingAgentsResponse)]
+    StakingAgents {
+    },
+
+    #[returns(PendingTransferResponse)]
+    PendingTransfer {
+        token_id: String,
+    },
+
+    #[returns(DenylistResponse)]
+    Denylist {
+    },
+
+    #[returns(StreakResponse)]
+    Streak {
+        address: String,
+    },
+
+    #[returns(StreakTiersResponse)]
+    StreakTiers {
+    },
+
+    #[returns(PendingChangesResponse)]
+    PendingChanges {
+    },
+
+    /// Voting module interface. `height` defaults to the current block and
+    /// includes changes made in it.
+    #[returns(VotingPowerAtHeightResponse)]
+    VotingPowerAtHeight {
+        address: String,
+        height: Option<u64>,
+    },
+
+    #[returns(TotalPowerAtHeightResponse)]
+    TotalPowerAtHeight {
+        height: Option<u64>,
+    },
+
+    #[returns(InfoResponse)]
+    Info {
+    },
+
+    #[returns(SponsoredResponse)]
+    Sponsored {
+        address: Addr
+    },
+
+    #[returns(PauseFlagsResponse)]
+    PauseFlags {
+    },
+
+    #[returns(NftClaimsResponse)]
+    NftClaims {
+        address: Addr
+    },
     
 }
 
@@ -71,12 +299,21 @@ pub struct ConfigResponse {
     pub fee_address: Addr,
     pub duration: u64,
     pub enabled: bool,
+    pub emergency: bool,
     pub current_airdrop_time: u64,
     pub start_airdrop: bool,
     pub current_time: u64,
     pub total_staked: u64,
     pub total_airdrop: Uint128,
-    pub locktime_fee: Uint128
+    pub airdrop_dust: Uint128,
+    pub unclaimed_rewards: Uint128,
+    pub locktime_fee: Uint128,
+    pub receipt_collection: Option<Addr>,
+    pub max_per_user: Option<u64>,
+    pub max_total_staked: Option<u64>,
+    pub weighting: Weighting,
+    pub min_stake_age: u64,
+    pub time_weighted: bool
 }
 
 #[cw_serde]
@@ -89,12 +326,144 @@ pub struct TotalLockedResponse {
     pub count: Uint128,
 }
 
+#[cw_serde]
+pub struct LockTiersResponse {
+    pub tiers: Vec<LockTier>,
+    pub max_lock_duration: u64,
+}
+
+#[cw_serde]
+pub struct RewardRecipientResponse {
+    pub address: Addr,
+    pub recipient: Addr,
+}
+
+#[cw_serde]
+pub struct OperatorResponse {
+    pub approved: bool,
+    pub expires: Option<Expiration>,
+}
+
+#[cw_serde]
+pub struct DelegationResponse {
+    pub validator: String,
+    /// Shares of the contract's delegation to `validator`.
+    pub shares: Uint128,
+    /// What the shares are worth now, accrued staking rewards included.
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+pub struct DelegationsResponse {
+    pub claim_mode: ClaimMode,
+    pub delegations: Vec<DelegationResponse>,
+    pub undelegations: ClaimsResponse,
+}
+
+#[cw_serde]
+pub struct VotingPowerAtHeightResponse {
+    pub power: Uint128,
+    pub height: u64,
+}
+
+#[cw_serde]
+pub struct TotalPowerAtHeightResponse {
+    pub power: Uint128,
+    pub height: u64,
+}
+
+#[cw_serde]
+pub struct InfoResponse {
+    pub info: ContractVersion,
+}
+
+#[cw_serde]
+pub struct PendingChangeResponse {
+    pub id: u64,
+    pub change: ConfigChange,
+    pub eta: u64,
+}
+
+#[cw_serde]
+pub struct PendingChangesResponse {
+    pub changes: Vec<PendingChangeResponse>,
+    pub delay: u64,
+}
+
+#[cw_serde]
+pub struct NftStreakResponse {
+    pub token_id: String,
+    pub streak: u32,
+    pub multiplier: Decimal,
+}
+
+#[cw_serde]
+pub struct StreakResponse {
+    pub address: Addr,
+    pub streak: u32,
+    pub nfts: Vec<NftStreakResponse>,
+}
+
+#[cw_serde]
+pub struct StreakTiersResponse {
+    pub tiers: Vec<StreakTier>,
+    pub renewal_window: u64,
+}
+
+#[cw_serde]
+pub struct DenylistResponse {
+    pub mode: DenylistMode,
+    pub tokens: Vec<String>,
+    pub addresses: Vec<Addr>,
+}
+
+#[cw_serde]
+pub struct PendingTransferResponse {
+    pub transfer: Option<PendingTransfer>,
+}
+
+#[cw_serde]
+pub struct StakingAgentsResponse {
+    pub agents: Vec<Addr>,
+}
+
+#[cw_serde]
+pub struct HooksResponse {
+    pub hooks: Vec<String>,
+    pub fatal: bool,
+}
+
+#[cw_serde]
+pub struct SponsoredResponse {
+    pub address: Addr,
+    pub amount: Uint128,
+}
+
+#[cw_serde]
+pub struct PauseFlagsResponse {
+    pub enabled: bool,
+    pub pauser: Option<Addr>,
+    pub flags: PauseFlags,
+}
+
+#[cw_serde]
+pub struct NftClaimsResponse {
+    pub unbonding_period: u64,
+    pub claims: Vec<NftClaim>,
+}
+
 #[cw_serde]
 pub struct StakedNftResponse {
     pub account_address: Addr,
     pub nft_id: String,
     pub airdrop: Uint128,
-    pub lock_time: u64
+    pub lock_time: u64,
+    pub auto_renew: bool,
+    pub receipt: bool,
+    pub staked_at: u64,
+    /// Renewal period and early-unstake fee this position is held to.
+    pub duration: u64,
+    pub locktime_fee: Uint128
 }
 
 #[cw_serde]
@@ -102,10 +471,30 @@ pub struct StakedNftsResponse {
     pub nft_maps: Vec<StakedNftResponse>,
 }
 
+/// Payload of `SendNft` to this contract. An empty payload stakes with the
+/// default options.
 #[cw_serde]
 pub enum NftReceiveMsg {
+    /// Legacy format; `sender` and `token_id` must repeat the transfer.
     Stake {
         sender: String,
-        token_id: String
-    }
+        token_id: String,
+        auto_renew: Option<bool>
+    },
+    StakeV2 {
+        /// Index into the lock tiers; the lock starts at that tier's duration.
+        lock_tier: Option<u32>,
+        auto_renew: Option<bool>,
+        /// Sets the account's reward recipient, as `SetRewardRecipient` would.
+        /// Not allowed when staking for another account.
+        reward_recipient: Option<Addr>,
+        /// Account credited with the stake. Defaults to the NFT sender; any
+        /// other address needs the sender to be a staking agent.
+        beneficiary: Option<Addr>,
+        /// Mints a transferable receipt for the position to the staker.
+        /// Sending the receipt back to this contract redeems it: the receipt
+        /// is burned and the sender holds the position directly, as needed
+        /// before unstaking.
+        receipt: Option<bool>,
+    },
 }
\ No newline at end of file
@@ -1,6 +1,8 @@
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Addr, Uint128, BlockInfo};
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::{Addr, Uint128, BlockInfo, Decimal, Empty};
+use cw_storage_plus::{Item, Map, SnapshotItem, SnapshotMap, Strategy};
+use cw_utils::Expiration;
+use cw_controllers::{Claims, Hooks};
 
 #[cw_serde]
 pub struct Config {
@@ -10,6 +12,36 @@ pub struct Config {
     pub enabled: bool,
     pub collection_address: Addr,
     pub duration: u64,
+    /// Most NFTs one account may have staked at once.
+    #[serde(default)]
+    pub max_per_user: Option<u64>,
+    /// Most NFTs the contract holds in stake at once.
+    #[serde(default)]
+    pub max_total_staked: Option<u64>,
+    #[serde(default)]
+    pub weighting: Weighting,
+    /// Seconds an NFT must have been staked to share in an airdrop.
+    #[serde(default)]
+    pub min_stake_age: u64,
+    /// Scales each share by the part of the airdrop epoch, since the last
+    /// `AirdropRestart`, that the NFT spent staked.
+    #[serde(default)]
+    pub time_weighted: bool,
+}
+
+/// How an account's NFTs are weighted against each other in an airdrop.
+/// `Diminishing` counts the account's eligible NFTs past the first
+/// `threshold` at `factor` of their normal weight.
+#[cw_serde]
+pub enum Weighting {
+    Linear {},
+    Diminishing { threshold: u64, factor: Decimal },
+}
+
+impl Default for Weighting {
+    fn default() -> Self {
+        Weighting::Linear {}
+    }
 }
 
 #[cw_serde]
@@ -18,6 +50,138 @@ pub struct  NftInfo {
     pub lock_time: u64,
     pub airdrop: Uint128,
     pub collection_address: Addr,
+    #[serde(default)]
+    pub auto_renew: bool,
+    /// A receipt with the same token id was minted in the receipt collection;
+    /// its holder owns this position.
+    #[serde(default)]
+    pub receipt: bool,
+    /// Block time the NFT was staked. Unlike `lock_time` it never moves, so
+    /// restakes and transfers keep the position's age. Zero for positions
+    /// staked before it was recorded.
+    #[serde(default)]
+    pub staked_at: u64,
+    /// Lock periods renewed back to back. Auto-renewed periods are added on
+    /// top when read through `get_streak`.
+    #[serde(default)]
+    pub streak: u32,
+    /// Lock period of the last stake, restake or tier extension: the chosen
+    /// lock tier's duration, else `Config.duration` at the time. Auto-renew rolls the
+    /// lock by this period. `None` for older positions, which follow the
+    /// current config.
+    #[serde(default)]
+    pub duration: Option<u64>,
+    /// Early-unstake fee in force when the NFT was staked or last restaked.
+    /// `None` for older positions, which pay the current fee.
+    #[serde(default)]
+    pub locktime_fee: Option<Uint128>,
+    /// Lock tier multiplier of the lock set by the last stake, restake or
+    /// extension. It weighs the NFT's airdrop share and voting power until
+    /// the next one.
+    /// `None` for older positions, which weigh one.
+    #[serde(default)]
+    pub multiplier: Option<Decimal>,
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub struct PauseFlags {
+    pub stake: bool,
+    pub unstake: bool,
+    pub claim: bool,
+    pub restake: bool,
+    pub airdrop: bool,
+}
+
+/// Where claimed rewards go. `Delegate` stakes them with `validator` from
+/// the contract's own account, for shares of that delegation; `Vault` deposits them into the configured vault.
+#[cw_serde]
+pub enum ClaimMode {
+    Send {},
+    Delegate { validator: String },
+    Vault {},
+}
+
+/// What staking a denylisted token does. `Reject` refuses the stake;
+/// `Exclude` accepts it but leaves it out of airdrops.
+#[cw_serde]
+pub enum DenylistMode {
+    Reject {},
+    Exclude {},
+}
+
+#[cw_serde]
+pub struct LockTier {
+    pub duration: u64,
+    pub multiplier: Decimal,
+}
+
+/// A sensitive parameter change waiting out the timelock.
+#[cw_serde]
+pub enum ConfigChange {
+    Owner {
+        owner: Addr,
+    },
+    FeeAddress {
+        fee_address: Addr,
+    },
+    Duration {
+        duration: u64,
+    },
+    /// Only the fields that differed from the config when it was queued, so
+    /// applying it leaves later changes to the other fields alone.
+    Config {
+        new_owner: Option<Addr>,
+        new_fee_address: Option<Addr>,
+        new_collection_address: Option<Addr>,
+        new_duration: Option<u64>,
+        new_locktime_fee: Option<Uint128>,
+    },
+    TimelockDelay {
+        delay: u64,
+    },
+    UnbondingPeriod {
+        unbonding_period: u64,
+    },
+    LockTiers {
+        tiers: Vec<LockTier>,
+        max_lock_duration: u64,
+    },
+    StakingLimits {
+        max_per_user: Option<u64>,
+        max_total_staked: Option<u64>,
+        weighting: Weighting,
+    },
+    Vault {
+        vault: Option<Addr>,
+    },
+}
+
+#[cw_serde]
+pub struct PendingChange {
+    pub change: ConfigChange,
+    /// Earliest block time the change may be executed.
+    pub eta: u64,
+}
+
+/// Reward multiplier for NFTs whose streak is at least `streak`.
+#[cw_serde]
+pub struct StreakTier {
+    pub streak: u32,
+    pub multiplier: Decimal,
+}
+
+#[cw_serde]
+pub struct NftClaim {
+    pub nft_id: String,
+    pub collection_address: Addr,
+    pub release_at: u64,
+}
+
+#[cw_serde]
+pub struct PendingTransfer {
+    pub from: Addr,
+    pub to: Addr,
 }
 
 #[cw_serde]
@@ -39,6 +203,16 @@ pub const START_AIRDROP: Item<bool> = Item::new(START_AIRDROP_KEY);
 pub const TOTAL_AIRDROP_KEY: &str = "total_airdrop";
 pub const TOTAL_AIRDROP: Item<Uint128> = Item::new(TOTAL_AIRDROP_KEY);
 
+/// Rewards credited to staked NFTs and not yet claimed or forfeited.
+pub const UNCLAIMED_REWARDS_KEY: &str = "unclaimed_rewards";
+pub const UNCLAIMED_REWARDS: Item<Uint128> = Item::new(UNCLAIMED_REWARDS_KEY);
+
+pub const AIRDROP_DUST_KEY: &str = "airdrop_dust";
+pub const AIRDROP_DUST: Item<Uint128> = Item::new(AIRDROP_DUST_KEY);
+
+pub const AIRDROP_SPONSORS_PREFIX: &str = "airdrop_sponsors";
+pub const AIRDROP_SPONSORS: Map<Addr, Uint128> = Map::new(AIRDROP_SPONSORS_PREFIX);
+
 pub const TOTAL_STAKED_KEY: &str = "total_staked";
 pub const TOTAL_STAKED: Item<u64> = Item::new(TOTAL_STAKED_KEY);
 
@@ -47,3 +221,138 @@ pub const LOCKTIME_FEE: Item<Uint128> = Item::new(LOCKTIME_FEE_KEY);
 
 pub const ACCOUNT_MAP_PREFIX: &str = "account_map";
 pub const ACCOUNT_MAP: Map<Addr, UserInfo> = Map::new(ACCOUNT_MAP_PREFIX);
+
+pub const LOCK_TIERS_KEY: &str = "lock_tiers";
+pub const LOCK_TIERS: Item<Vec<LockTier>> = Item::new(LOCK_TIERS_KEY);
+
+pub const MAX_LOCK_DURATION_KEY: &str = "max_lock_duration";
+pub const MAX_LOCK_DURATION: Item<u64> = Item::new(MAX_LOCK_DURATION_KEY);
+
+pub const UNBONDING_PERIOD_KEY: &str = "unbonding_period";
+pub const UNBONDING_PERIOD: Item<u64> = Item::new(UNBONDING_PERIOD_KEY);
+
+pub const NFT_CLAIMS_PREFIX: &str = "nft_claims";
+pub const NFT_CLAIMS: Map<Addr, Vec<NftClaim>> = Map::new(NFT_CLAIMS_PREFIX);
+
+pub const EMERGENCY_KEY: &str = "emergency";
+pub const EMERGENCY: Item<bool> = Item::new(EMERGENCY_KEY);
+
+pub const PAUSE_FLAGS_KEY: &str = "pause_flags";
+pub const PAUSE_FLAGS: Item<PauseFlags> = Item::new(PAUSE_FLAGS_KEY);
+
+pub const PAUSER_KEY: &str = "pauser";
+pub const PAUSER: Item<Option<Addr>> = Item::new(PAUSER_KEY);
+
+pub const REWARD_RECIPIENT_PREFIX: &str = "reward_recipient";
+pub const REWARD_RECIPIENT: Map<Addr, Addr> = Map::new(REWARD_RECIPIENT_PREFIX);
+
+pub const OPERATORS_PREFIX: &str = "operators";
+pub const OPERATORS: Map<(Addr, Addr), Expiration> = Map::new(OPERATORS_PREFIX);
+
+pub const CLAIM_MODE_PREFIX: &str = "claim_mode";
+pub const CLAIM_MODE: Map<Addr, ClaimMode> = Map::new(CLAIM_MODE_PREFIX);
+
+pub const VAULT_KEY: &str = "vault";
+pub const VAULT: Item<Option<Addr>> = Item::new(VAULT_KEY);
+
+/// Shares each account holds of the contract's delegation to a validator.
+pub const DELEGATIONS_PREFIX: &str = "delegations";
+pub const DELEGATIONS: Map<(Addr, String), Uint128> = Map::new(DELEGATIONS_PREFIX);
+
+/// Sum of `DELEGATIONS` per validator. The delegation's bonded amount and
+/// accrued rewards split over it give the value of one share.
+pub const VALIDATOR_DELEGATIONS_PREFIX: &str = "validator_delegations";
+pub const VALIDATOR_DELEGATIONS: Map<String, Uint128> = Map::new(VALIDATOR_DELEGATIONS_PREFIX);
+
+pub const UNDELEGATION_PERIOD_KEY: &str = "undelegation_period";
+pub const UNDELEGATION_PERIOD: Item<u64> = Item::new(UNDELEGATION_PERIOD_KEY);
+
+pub const UNDELEGATION_CLAIMS: Claims = Claims::new("undelegation_claims");
+
+pub const HOOKS: Hooks = Hooks::new("hooks");
+
+pub const HOOK_ERRORS_FATAL_KEY: &str = "hook_errors_fatal";
+pub const HOOK_ERRORS_FATAL: Item<bool> = Item::new(HOOK_ERRORS_FATAL_KEY);
+
+/// Voting power per account and in total, snapshotted at every change so
+/// DAO modules can read it back at past heights.
+pub const VOTING_POWER: SnapshotMap<Addr, Uint128> = SnapshotMap::new(
+    "voting_power",
+    "voting_power__checkpoints",
+    "voting_power__changelog",
+    Strategy::EveryBlock,
+);
+pub const TOTAL_POWER: SnapshotItem<Uint128> = SnapshotItem::new(
+    "total_power",
+    "total_power__checkpoints",
+    "total_power__changelog",
+    Strategy::EveryBlock,
+);
+
+/// Senders allowed to stake NFTs on behalf of another address.
+pub const STAKING_AGENTS_PREFIX: &str = "staking_agents";
+pub const STAKING_AGENTS: Map<Addr, Empty> = Map::new(STAKING_AGENTS_PREFIX);
+
+/// Child cw721 collection that mints receipts for staked positions.
+pub const RECEIPT_COLLECTION_KEY: &str = "receipt_collection";
+pub const RECEIPT_COLLECTION: Item<Option<Addr>> = Item::new(RECEIPT_COLLECTION_KEY);
+
+/// Account whose `UserInfo` holds each receipt-backed position, keyed by
+/// token id. It trails the receipt owner until the position is synced.
+pub const RECEIPT_HOLDERS_PREFIX: &str = "receipt_holders";
+pub const RECEIPT_HOLDERS: Map<String, Addr> = Map::new(RECEIPT_HOLDERS_PREFIX);
+
+/// Receipt token id of each receipt-backed position, keyed by token id, and
+/// the reverse lookup for receipts sent back to be redeemed.
+pub const RECEIPT_IDS_PREFIX: &str = "receipt_ids";
+pub const RECEIPT_IDS: Map<String, String> = Map::new(RECEIPT_IDS_PREFIX);
+
+pub const RECEIPT_TOKENS_PREFIX: &str = "receipt_tokens";
+pub const RECEIPT_TOKENS: Map<String, String> = Map::new(RECEIPT_TOKENS_PREFIX);
+
+/// Receipts voided per token id by an emergency return. A voided receipt
+/// cannot be burned by the contract and keeps its id, so the next receipt
+/// for the token is minted under a fresh one.
+pub const VOIDED_RECEIPTS_PREFIX: &str = "voided_receipts";
+pub const VOIDED_RECEIPTS: Map<String, u32> = Map::new(VOIDED_RECEIPTS_PREFIX);
+
+/// Stake transfers waiting for the recipient to accept, keyed by token id.
+pub const PENDING_TRANSFERS_PREFIX: &str = "pending_transfers";
+pub const PENDING_TRANSFERS: Map<String, PendingTransfer> = Map::new(PENDING_TRANSFERS_PREFIX);
+
+/// Token ids and holder addresses kept out of rewards. Denylisted addresses
+/// cannot stake at all.
+pub const DENIED_TOKENS_PREFIX: &str = "denied_tokens";
+pub const DENIED_TOKENS: Map<String, Empty> = Map::new(DENIED_TOKENS_PREFIX);
+
+pub const DENIED_ADDRESSES_PREFIX: &str = "denied_addresses";
+pub const DENIED_ADDRESSES: Map<Addr, Empty> = Map::new(DENIED_ADDRESSES_PREFIX);
+
+pub const DENYLIST_MODE_KEY: &str = "denylist_mode";
+pub const DENYLIST_MODE: Item<DenylistMode> = Item::new(DENYLIST_MODE_KEY);
+
+pub const STREAK_TIERS_KEY: &str = "streak_tiers";
+pub const STREAK_TIERS: Item<Vec<StreakTier>> = Item::new(STREAK_TIERS_KEY);
+
+/// Seconds after a lock ends in which a restake still continues the streak.
+pub const RENEWAL_WINDOW_KEY: &str = "renewal_window";
+pub const RENEWAL_WINDOW: Item<u64> = Item::new(RENEWAL_WINDOW_KEY);
+
+/// Renewals an account made in a row, reset by a late restake or an unstake.
+pub const USER_STREAKS_PREFIX: &str = "user_streaks";
+pub const USER_STREAKS: Map<Addr, u32> = Map::new(USER_STREAKS_PREFIX);
+
+/// End of the lock period the account streak was last raised for. Further
+/// on-time restakes before it do not raise the streak again.
+pub const USER_STREAK_PERIODS_PREFIX: &str = "user_streak_periods";
+pub const USER_STREAK_PERIODS: Map<Addr, u64> = Map::new(USER_STREAK_PERIODS_PREFIX);
+
+/// Seconds a queued `ConfigChange` must wait before it can be executed.
+pub const TIMELOCK_DELAY_KEY: &str = "timelock_delay";
+pub const TIMELOCK_DELAY: Item<u64> = Item::new(TIMELOCK_DELAY_KEY);
+
+pub const NEXT_CHANGE_ID_KEY: &str = "next_change_id";
+pub const NEXT_CHANGE_ID: Item<u64> = Item::new(NEXT_CHANGE_ID_KEY);
+
+pub const PENDING_CHANGES_PREFIX: &str = "pending_changes";
+pub const PENDING_CHANGES: Map<u64, PendingChange> = Map::new(PENDING_CHANGES_PREFIX);
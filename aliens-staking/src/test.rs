@@ -1,129 +1,2329 @@
-use near_units::{parse_gas, parse_near};
-use serde_json::json;
-use workspaces::prelude::*;
-use workspaces::{network::Sandbox, Account, Contract, Worker};
-
-const NFT_WASM_FILEPATH: &str = "../../res/non_fungible_token.wasm";
-
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    // initiate environemnt
-    let worker = workspaces::sandbox().await?;
-
-    // deploy contracts
-    let nft_wasm = std::fs::read(NFT_WASM_FILEPATH)?;
-    let nft_contract = worker.dev_deploy(&nft_wasm).await?;
-    let tr_wasm = std::fs::read(TR_WASM_FILEPATH)?;
-    let tr_contract = worker.dev_deploy(&tr_wasm).await?;
-    let ar_wasm = std::fs::read(AR_WASM_FILEPATH)?;
-    let ar_contract = worker.dev_deploy(&ar_wasm).await?;
-
-    // create accounts
-    let owner = worker.root_account().unwrap();
-    let alice = owner
-        .create_subaccount(&worker, "alice")
-        .initial_balance(parse_near!("30 N"))
-        .transact()
-        .await?
-        .into_result()?;
-
-    // Initialize contracts
-    nft_contract
-        .call(&worker, "new_default_meta")
-        .args_json(serde_json::json!({
-            "owner_id": owner.id()
-        }))?
-        .transact()
-        .await?;
-    tr_contract
-        .call(&worker, "new")
-        .args_json(serde_json::json!({
-            "non_fungible_token_account_id": nft_contract.id()
-        }))?
-        .transact()
-        .await?;
-    ar_contract
-        .call(&worker, "new")
-        .args_json(serde_json::json!({
-            "non_fungible_token_account_id": nft_contract.id()
-        }))?
-        .transact()
-        .await?;
-
-    // begin tests
-    test_simple_approve(&owner, &alice, &nft_contract, &worker).await?;
-    Ok(())
-}
-
-async fn test_simple_approve(
-    owner: &Account,
-    user: &Account,
-    nft_contract: &Contract,
-    worker: &Worker<Sandbox>,
-) -> anyhow::Result<()> {
-    owner
-        .call(&worker, nft_contract.id(), "nft_mint")
-        .args_json(json!({
-            "token_id": "0",
-            "receiver_id": owner.id(),
-            "token_metadata": {
-                "title": "Olympus Mons",
-                "description": "The tallest mountain in the charted solar system",
-                "copies": 10000,
+use anyhow::Result as AnyResult;
+use cosmwasm_std::{coin, coins, to_binary, Addr, Binary, Coin, Decimal, Empty, Response, StdError, StdResult, Uint128};
+use cw_multi_test::{App, AppResponse, Contract, ContractWrapper, Executor};
+
+use crate::contract::{execute, instantiate, query, reply};
+use crate::msg::{
+    ConfigResponse, DelegationsResponse, ExecuteMsg, HooksResponse, InfoResponse, InstantiateMsg, NftClaimsResponse, NftReceiveMsg, OperatorResponse,
+    PendingTransferResponse, QueryMsg,
+    RewardRecipientResponse, SponsoredResponse, StakeChangeKind, StakeChangedExecuteMsg, StakeChangedHookMsg, StakedNftsResponse,
+    DenylistResponse, PendingChangeResponse, PendingChangesResponse, StakingAgentsResponse, StreakResponse, StreakTiersResponse, TotalEarnedResponse, TotalLockedResponse, TotalPowerAtHeightResponse, VaultExecuteMsg, VotingPowerAtHeightResponse,
+};
+use crate::state::{ClaimMode, ConfigChange, DenylistMode, LockTier, PauseFlags, StreakTier, Weighting};
+use crate::ContractError;
+
+const OWNER: &str = "owner";
+const FEE_ADDRESS: &str = "fee_address";
+const ALICE: &str = "alice";
+const BOB: &str = "bob";
+const DENOM: &str = "uaxl";
+const OTHER_DENOM: &str = "uother";
+const DURATION: u64 = 86_400;
+const TIMELOCK_DELAY: u64 = 2 * 86_400;
+const LOCKTIME_FEE: u128 = 100;
+
+/// Just enough of a cw721 collection to mint, transfer and `SendNft` into
+/// the staking contract, and to serve as the receipt collection.
+mod mock_cw721 {
+    use cosmwasm_schema::cw_serde;
+    use cosmwasm_std::{
+        to_binary, Addr, Binary, Deps, DepsMut, Empty, Env, MessageInfo, Response, StdError,
+        StdResult,
+    };
+    use cw721::{Cw721ReceiveMsg, OwnerOfResponse};
+    use cw_storage_plus::Map;
+
+    #[cw_serde]
+    pub enum ExecuteMsg {
+        Mint { token_id: String, owner: String, token_uri: Option<String>, extension: Option<Empty> },
+        Burn { token_id: String },
+        ApproveAll { operator: String, expires: Option<cw_utils::Expiration> },
+        TransferNft { recipient: String, token_id: String },
+        SendNft { contract: String, token_id: String, msg: Binary },
+    }
+
+    #[cw_serde]
+    pub enum QueryMsg {
+        OwnerOf { token_id: String, include_expired: Option<bool> },
+    }
+
+    const OWNERS: Map<&str, Addr> = Map::new("owners");
+    const OPERATORS: Map<(&Addr, &Addr), Empty> = Map::new("operators");
+
+    fn check_owner(deps: Deps, sender: &Addr, token_id: &str) -> StdResult<()> {
+        let owner = OWNERS.load(deps.storage, token_id)?;
+        if owner != *sender && !OPERATORS.has(deps.storage, (&owner, sender)) {
+            return Err(StdError::generic_err("Unauthorized"));
+        }
+        Ok(())
+    }
+
+    pub fn instantiate(_deps: DepsMut, _env: Env, _info: MessageInfo, _msg: Empty) -> StdResult<Response> {
+        Ok(Response::new())
+    }
+
+    pub fn execute(deps: DepsMut, _env: Env, info: MessageInfo, msg: ExecuteMsg) -> StdResult<Response> {
+        match msg {
+            ExecuteMsg::Mint { token_id, owner, .. } => {
+                if OWNERS.has(deps.storage, &token_id) {
+                    return Err(StdError::generic_err("token_id already claimed"));
+                }
+                OWNERS.save(deps.storage, &token_id, &deps.api.addr_validate(&owner)?)?;
+                Ok(Response::new())
+            }
+            ExecuteMsg::Burn { token_id } => {
+                check_owner(deps.as_ref(), &info.sender, &token_id)?;
+                OWNERS.remove(deps.storage, &token_id);
+                Ok(Response::new())
+            }
+            ExecuteMsg::ApproveAll { operator, .. } => {
+                OPERATORS.save(deps.storage, (&info.sender, &deps.api.addr_validate(&operator)?), &Empty {})?;
+                Ok(Response::new())
+            }
+            ExecuteMsg::TransferNft { recipient, token_id } => {
+                check_owner(deps.as_ref(), &info.sender, &token_id)?;
+                OWNERS.save(deps.storage, &token_id, &deps.api.addr_validate(&recipient)?)?;
+                Ok(Response::new())
+            }
+            ExecuteMsg::SendNft { contract, token_id, msg } => {
+                check_owner(deps.as_ref(), &info.sender, &token_id)?;
+                OWNERS.save(deps.storage, &token_id, &deps.api.addr_validate(&contract)?)?;
+                let receive = Cw721ReceiveMsg {
+                    sender: info.sender.to_string(),
+                    token_id,
+                    msg,
+                };
+                Ok(Response::new().add_message(receive.into_cosmos_msg(contract)?))
+            }
+        }
+    }
+
+    pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+        match msg {
+            QueryMsg::OwnerOf { token_id, .. } => to_binary(&OwnerOfResponse {
+                owner: OWNERS.load(deps.storage, &token_id)?.to_string(),
+                approvals: vec![],
+            }),
+        }
+    }
+}
+
+/// Accepts `VaultExecuteMsg::Deposit` and keeps whatever funds come with it.
+fn vault_contract() -> Box<dyn Contract<Empty>> {
+    Box::new(ContractWrapper::new(
+        |_deps, _env, _info, _msg: VaultExecuteMsg| -> StdResult<Response> { Ok(Response::new()) },
+        |_deps, _env, _info, _msg: Empty| -> StdResult<Response> { Ok(Response::new()) },
+        |_deps, _env, _msg: Empty| -> StdResult<Binary> { to_binary(&Empty {}) },
+    ))
+}
+
+/// Hook receiver that records every event and rejects claims.
+mod mock_hook {
+    use super::*;
+    use cosmwasm_std::{Deps, DepsMut, Env, MessageInfo, StdError};
+    use cw_storage_plus::Item;
+
+    pub const EVENTS: Item<Vec<StakeChangedHookMsg>> = Item::new("events");
+
+    pub fn instantiate(deps: DepsMut, _env: Env, _info: MessageInfo, _msg: Empty) -> StdResult<Response> {
+        EVENTS.save(deps.storage, &vec![])?;
+        Ok(Response::new())
+    }
+
+    pub fn execute(deps: DepsMut, _env: Env, _info: MessageInfo, msg: StakeChangedExecuteMsg) -> StdResult<Response> {
+        let StakeChangedExecuteMsg::StakeChangeHook(event) = msg;
+        if event.kind == StakeChangeKind::Claim {
+            return Err(StdError::generic_err("claims not accepted"));
+        }
+        EVENTS.update(deps.storage, |mut events| -> StdResult<_> {
+            events.push(event);
+            Ok(events)
+        })?;
+        Ok(Response::new())
+    }
+
+    pub fn query(deps: Deps, _env: Env, _msg: Empty) -> StdResult<Binary> {
+        to_binary(&EVENTS.load(deps.storage)?)
+    }
+}
+
+fn hook_contract() -> Box<dyn Contract<Empty>> {
+    Box::new(ContractWrapper::new(mock_hook::execute, mock_hook::instantiate, mock_hook::query))
+}
+
+/// Hook receiver that rejects every event.
+fn failing_hook_contract() -> Box<dyn Contract<Empty>> {
+    Box::new(ContractWrapper::new(
+        |_deps, _env, _info, _msg: StakeChangedExecuteMsg| -> StdResult<Response> { Err(StdError::generic_err("hook is down")) },
+        |_deps, _env, _info, _msg: Empty| -> StdResult<Response> { Ok(Response::new()) },
+        |_deps, _env, _msg: Empty| -> StdResult<Binary> { to_binary(&Empty {}) },
+    ))
+}
+
+fn staking_contract() -> Box<dyn Contract<Empty>> {
+    Box::new(ContractWrapper::new(execute, instantiate, query).with_reply(reply))
+}
+
+fn cw721_contract() -> Box<dyn Contract<Empty>> {
+    Box::new(ContractWrapper::new(
+        mock_cw721::execute,
+        mock_cw721::instantiate,
+        mock_cw721::query,
+    ))
+}
+
+struct Suite {
+    app: App,
+    staking: Addr,
+    collection: Addr,
+}
+
+impl Suite {
+    /// Alice owns tokens 1-4 and Bob owns 5-6. The locktime fee is lowered
+    /// to `LOCKTIME_FEE` and the staking window is open.
+    fn new() -> Self {
+        let mut app = App::new(|router, _api, storage| {
+            for (address, amount) in [(OWNER, 1_000_000u128), (ALICE, 10_000), (BOB, 10_000)] {
+                router
+                    .bank
+                    .init_balance(storage, &Addr::unchecked(address), vec![coin(amount, DENOM), coin(amount, OTHER_DENOM)])
+                    .unwrap();
+            }
+        });
+
+        let cw721_id = app.store_code(cw721_contract());
+        let collection = app
+            .instantiate_contract(cw721_id, Addr::unchecked(OWNER), &Empty {}, &[], "aliens", None)
+            .unwrap();
+        for (token_id, owner) in [("1", ALICE), ("2", ALICE), ("3", ALICE), ("4", ALICE), ("5", BOB), ("6", BOB)] {
+            app.execute_contract(
+                Addr::unchecked(OWNER),
+                collection.clone(),
+                &mock_cw721::ExecuteMsg::Mint {
+                    token_id: token_id.to_string(),
+                    owner: owner.to_string(),
+                    token_uri: None,
+                    extension: None,
+                },
+                &[],
+            )
+            .unwrap();
+        }
+
+        let staking_id = app.store_code(staking_contract());
+        let staking = app
+            .instantiate_contract(
+                staking_id,
+                Addr::unchecked(OWNER),
+                &InstantiateMsg {
+                    owner: Addr::unchecked(OWNER),
+                    fee_address: Addr::unchecked(FEE_ADDRESS),
+                    collection_address: collection.clone(),
+                    native_token: DENOM.to_string(),
+                    duration: DURATION,
+                },
+                &[],
+                "staking",
+                None,
+            )
+            .unwrap();
+
+        let mut suite = Suite { app, staking, collection };
+        suite
+            .apply_change(ExecuteMsg::UpdateConfig {
+                new_owner: Addr::unchecked(OWNER),
+                new_fee_address: Addr::unchecked(FEE_ADDRESS),
+                new_collection_address: suite.collection.clone(),
+                new_duration: DURATION,
+                new_locktime_fee: Uint128::new(LOCKTIME_FEE),
+            })
+            .unwrap();
+        suite.execute(OWNER, ExecuteMsg::AirdropRestart {}, &[]).unwrap();
+        suite
+    }
+
+    fn execute(&mut self, sender: &str, msg: ExecuteMsg, funds: &[Coin]) -> AnyResult<AppResponse> {
+        self.app
+            .execute_contract(Addr::unchecked(sender), self.staking.clone(), &msg, funds)
+    }
+
+    /// Queues a timelocked change, waits out the delay and executes it.
+    fn apply_change(&mut self, msg: ExecuteMsg) -> AnyResult<AppResponse> {
+        let res = self.execute(OWNER, msg, &[])?;
+        let id = event_attributes(&res, "queue_change")
+            .into_iter()
+            .find(|(key, _)| key == "id")
+            .map(|(_, id)| id.parse().unwrap())
+            .unwrap();
+        self.advance(TIMELOCK_DELAY);
+        self.execute(OWNER, ExecuteMsg::ExecuteChange { id }, &[])
+    }
+
+    /// Instantiates the receipt collection and returns its address.
+    fn create_receipts(&mut self) -> Addr {
+        let code_id = self.app.store_code(cw721_contract());
+        self.execute(OWNER, ExecuteMsg::CreateReceiptCollection {
+            code_id,
+            name: "Staked Aliens".to_string(),
+            symbol: "sALIEN".to_string(),
+        }, &[])
+        .unwrap();
+        self.config().receipt_collection.unwrap()
+    }
+
+    fn receipt_owner(&self, receipt_id: &str) -> StdResult<String> {
+        let receipts = self.config().receipt_collection.unwrap();
+        let res: cw721::OwnerOfResponse = self.app.wrap().query_wasm_smart(
+            receipts,
+            &mock_cw721::QueryMsg::OwnerOf { token_id: receipt_id.to_string(), include_expired: None },
+        )?;
+        Ok(res.owner)
+    }
+
+    /// Sends a receipt back to the staking contract to redeem it.
+    fn redeem_receipt(&mut self, sender: &str, receipt_id: &str) -> AnyResult<AppResponse> {
+        let receipts = self.config().receipt_collection.unwrap();
+        self.app.execute_contract(
+            Addr::unchecked(sender),
+            receipts,
+            &mock_cw721::ExecuteMsg::SendNft {
+                contract: self.staking.to_string(),
+                token_id: receipt_id.to_string(),
+                msg: Binary::default(),
+            },
+            &[],
+        )
+    }
+
+    fn send_nft(&mut self, sender: &str, token_id: &str, msg: &NftReceiveMsg) -> AnyResult<AppResponse> {
+        self.send_nft_raw(sender, token_id, to_binary(msg).unwrap())
+    }
+
+    fn send_nft_raw(&mut self, sender: &str, token_id: &str, msg: Binary) -> AnyResult<AppResponse> {
+        self.app.execute_contract(
+            Addr::unchecked(sender),
+            self.collection.clone(),
+            &mock_cw721::ExecuteMsg::SendNft {
+                contract: self.staking.to_string(),
+                token_id: token_id.to_string(),
+                msg,
+            },
+            &[],
+        )
+    }
+
+    fn stake(&mut self, sender: &str, token_id: &str) -> AnyResult<AppResponse> {
+        self.send_nft(sender, token_id, &NftReceiveMsg::Stake {
+            sender: sender.to_string(),
+            token_id: token_id.to_string(),
+            auto_renew: None,
+        })
+    }
+
+    /// Funds the contract from the owner and distributes `amount`.
+    fn airdrop(&mut self, amount: u128) -> AnyResult<AppResponse> {
+        self.app
+            .send_tokens(Addr::unchecked(OWNER), self.staking.clone(), &coins(amount, DENOM))
+            .unwrap();
+        self.execute(OWNER, ExecuteMsg::Airdrop { airdrop_amount: Uint128::new(amount) }, &[])
+    }
+
+    fn advance(&mut self, seconds: u64) {
+        self.app.update_block(|block| {
+            block.time = block.time.plus_seconds(seconds);
+            block.height += seconds / 5;
+        });
+    }
+
+    fn now(&self) -> u64 {
+        self.app.block_info().time.seconds()
+    }
+
+    fn query<T: serde::de::DeserializeOwned>(&self, msg: &QueryMsg) -> T {
+        self.app.wrap().query_wasm_smart(self.staking.clone(), msg).unwrap()
+    }
+
+    fn config(&self) -> ConfigResponse {
+        self.query(&QueryMsg::GetConfig {})
+    }
+
+    fn staked_nfts(&self, address: &str) -> StakedNftsResponse {
+        self.query(&QueryMsg::StakedNfts { address: Addr::unchecked(address) })
+    }
+
+    fn nft_owner(&self, token_id: &str) -> String {
+        let response: cw721::OwnerOfResponse = self
+            .app
+            .wrap()
+            .query_wasm_smart(self.collection.clone(), &mock_cw721::QueryMsg::OwnerOf {
+                token_id: token_id.to_string(),
+                include_expired: None,
+            })
+            .unwrap();
+        response.owner
+    }
+
+    fn balance(&self, address: &str) -> u128 {
+        self.app.wrap().query_balance(address, DENOM).unwrap().amount.u128()
+    }
+}
+
+/// Attributes of the `wasm-aliens_staking/<action>` event in `res`.
+fn event_attributes(res: &AppResponse, action: &str) -> Vec<(String, String)> {
+    let ty = format!("wasm-aliens_staking/{}", action);
+    res.events
+        .iter()
+        .find(|event| event.ty == ty)
+        .unwrap_or_else(|| panic!("missing event {}", ty))
+        .attributes
+        .iter()
+        .filter(|attr| attr.key != "_contract_addr")
+        .map(|attr| (attr.key.clone(), attr.value.clone()))
+        .collect()
+}
+
+fn assert_error(result: AnyResult<AppResponse>, expected: ContractError) {
+    assert_eq!(result.unwrap_err().downcast::<ContractError>().unwrap(), expected);
+}
+
+#[test]
+fn stake_via_send_nft() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+
+    assert_eq!(suite.nft_owner("1"), suite.staking.to_string());
+    let staked = suite.staked_nfts(ALICE).nft_maps;
+    assert_eq!(staked.len(), 1);
+    assert_eq!(staked[0].nft_id, "1");
+    assert_eq!(staked[0].lock_time, suite.now() + DURATION);
+    assert_eq!(suite.config().total_staked, 1);
+
+    let locked: TotalLockedResponse = suite.query(&QueryMsg::GetTotalLocked {});
+    assert_eq!(locked.count, Uint128::new(1));
+}
+
+#[test]
+fn queries_for_unknown_and_malformed_addresses() {
+    let suite = Suite::new();
+
+    // Addresses that never staked get empty results rather than errors.
+    assert!(suite.staked_nfts("carol").nft_maps.is_empty());
+    let earned: TotalEarnedResponse = suite.query(&QueryMsg::GetTotalEarned { address: Addr::unchecked("carol") });
+    assert_eq!(earned.total_earned, Uint128::zero());
+    let claims: NftClaimsResponse = suite.query(&QueryMsg::NftClaims { address: Addr::unchecked("carol") });
+    assert!(claims.claims.is_empty());
+    let locked: TotalLockedResponse = suite.query(&QueryMsg::GetTotalLocked {});
+    assert_eq!(locked.count, Uint128::zero());
+
+    let malformed = [
+        QueryMsg::StakedNfts { address: Addr::unchecked("Carol") },
+        QueryMsg::GetTotalEarned { address: Addr::unchecked("") },
+        QueryMsg::Sponsored { address: Addr::unchecked("Carol") },
+        QueryMsg::Operator { owner: Addr::unchecked(ALICE), operator: Addr::unchecked("Carol") },
+        QueryMsg::VotingPowerAtHeight { address: "Carol".to_string(), height: None },
+    ];
+    for msg in malformed.iter() {
+        let res: StdResult<Empty> = suite.app.wrap().query_wasm_smart(suite.staking.clone(), msg);
+        assert!(res.is_err(), "{:?} should fail", msg);
+    }
+}
+
+#[test]
+fn stake_rejects_foreign_collection_and_bad_payload() {
+    let mut suite = Suite::new();
+
+    let direct = ExecuteMsg::ReceiveNft(cw721::Cw721ReceiveMsg {
+        sender: ALICE.to_string(),
+        token_id: "1".to_string(),
+        msg: to_binary(&NftReceiveMsg::Stake {
+            sender: ALICE.to_string(),
+            token_id: "1".to_string(),
+            auto_renew: None,
+        })
+        .unwrap(),
+    });
+    assert_error(suite.execute(ALICE, direct, &[]), ContractError::InvalidCw721Token {});
+
+    let mismatched = NftReceiveMsg::Stake {
+        sender: ALICE.to_string(),
+        token_id: "2".to_string(),
+        auto_renew: None,
+    };
+    assert_error(suite.send_nft(ALICE, "1", &mismatched), ContractError::InvalidCw721Msg {});
+}
+
+#[test]
+fn stake_v2_payload_options() {
+    let mut suite = Suite::new();
+    suite
+        .apply_change(ExecuteMsg::UpdateLockTiers {
+            tiers: vec![
+                LockTier { duration: DURATION, multiplier: Decimal::one() },
+                LockTier { duration: 3 * DURATION, multiplier: Decimal::percent(200) },
+            ],
+            max_lock_duration: 3 * DURATION,
+        })
+        .unwrap();
+    let stake_v2 = |lock_tier, beneficiary: Option<&str>| NftReceiveMsg::StakeV2 {
+        lock_tier,
+        auto_renew: Some(true),
+        reward_recipient: Some(Addr::unchecked("carol")),
+        beneficiary: beneficiary.map(Addr::unchecked),
+        receipt: None,
+    };
+
+    // An empty payload stakes with the defaults.
+    suite.send_nft_raw(ALICE, "1", Binary::default()).unwrap();
+    let staked = suite.staked_nfts(ALICE).nft_maps;
+    assert_eq!(staked[0].lock_time, suite.now() + DURATION);
+    assert!(!staked[0].auto_renew);
+
+    assert_error(suite.send_nft(ALICE, "2", &stake_v2(Some(2), None)), ContractError::InvalidTier {});
+    assert_error(suite.send_nft(ALICE, "2", &stake_v2(None, Some(BOB))), ContractError::Unauthorized {});
+
+    suite.send_nft(ALICE, "2", &stake_v2(Some(1), Some(ALICE))).unwrap();
+    let staked = suite.staked_nfts(ALICE).nft_maps;
+    assert_eq!(staked[1].lock_time, suite.now() + 3 * DURATION);
+    assert!(staked[1].auto_renew);
+    let recipient: RewardRecipientResponse = suite.query(&QueryMsg::RewardRecipient { address: Addr::unchecked(ALICE) });
+    assert_eq!(recipient.recipient, Addr::unchecked("carol"));
+}
+
+#[test]
+fn staking_agents_stake_for_beneficiary() {
+    let mut suite = Suite::new();
+    let for_alice = NftReceiveMsg::StakeV2 {
+        lock_tier: None,
+        auto_renew: None,
+        reward_recipient: None,
+        beneficiary: Some(Addr::unchecked(ALICE)),
+        receipt: None,
+    };
+    assert_error(suite.send_nft(BOB, "5", &for_alice), ContractError::Unauthorized {});
+
+    let agents = ExecuteMsg::UpdateStakingAgents { add: vec![Addr::unchecked(BOB)], remove: vec![] };
+    assert_error(suite.execute(BOB, agents.clone(), &[]), ContractError::Unauthorized {});
+    suite.execute(OWNER, agents, &[]).unwrap();
+    let res: StakingAgentsResponse = suite.query(&QueryMsg::StakingAgents {});
+    assert_eq!(res.agents, vec![Addr::unchecked(BOB)]);
+
+    // An agent cannot redirect the beneficiary's rewards.
+    let redirect = NftReceiveMsg::StakeV2 {
+        lock_tier: None,
+        auto_renew: None,
+        reward_recipient: Some(Addr::unchecked(BOB)),
+        beneficiary: Some(Addr::unchecked(ALICE)),
+        receipt: None,
+    };
+    assert_error(suite.send_nft(BOB, "5", &redirect), ContractError::Unauthorized {});
+
+    suite.send_nft(BOB, "5", &for_alice).unwrap();
+    assert!(suite.staked_nfts(BOB).nft_maps.is_empty());
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].nft_id, "5");
+
+    // The position belongs to the beneficiary, so the NFT goes to them.
+    suite.advance(DURATION + 1);
+    suite.execute(ALICE, ExecuteMsg::Unstake { unstake_nft_id: "5".to_string() }, &[]).unwrap();
+    assert_eq!(suite.nft_owner("5"), ALICE);
+
+    suite
+        .execute(OWNER, ExecuteMsg::UpdateStakingAgents { add: vec![], remove: vec![Addr::unchecked(BOB)] }, &[])
+        .unwrap();
+    assert_error(suite.send_nft(BOB, "6", &for_alice), ContractError::Unauthorized {});
+}
+
+#[test]
+fn denylisted_tokens_and_addresses() {
+    let mut suite = Suite::new();
+    let deny = ExecuteMsg::UpdateDenylist {
+        add_tokens: vec!["1".to_string()],
+        remove_tokens: vec![],
+        add_addresses: vec![Addr::unchecked(BOB)],
+        remove_addresses: vec![],
+    };
+    assert_error(suite.execute(ALICE, deny.clone(), &[]), ContractError::Unauthorized {});
+    let malformed = ExecuteMsg::UpdateDenylist {
+        add_tokens: vec![],
+        remove_tokens: vec![],
+        add_addresses: vec![Addr::unchecked("Bob")],
+        remove_addresses: vec![],
+    };
+    assert!(suite.execute(OWNER, malformed, &[]).is_err());
+    suite.execute(OWNER, deny, &[]).unwrap();
+    let res: DenylistResponse = suite.query(&QueryMsg::Denylist {});
+    assert_eq!(res.mode, DenylistMode::Reject {});
+    assert_eq!(res.tokens, vec!["1".to_string()]);
+    assert_eq!(res.addresses, vec![Addr::unchecked(BOB)]);
+
+    assert_error(suite.stake(ALICE, "1"), ContractError::DeniedToken {});
+    assert_error(suite.stake(BOB, "5"), ContractError::DeniedAddress {});
+
+    // In exclude mode the token stakes but earns nothing.
+    suite.execute(OWNER, ExecuteMsg::UpdateDenylistMode { mode: DenylistMode::Exclude {} }, &[]).unwrap();
+    assert_error(suite.stake(BOB, "5"), ContractError::DeniedAddress {});
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    suite.airdrop(100).unwrap();
+    let nfts = suite.staked_nfts(ALICE).nft_maps;
+    assert_eq!(nfts[0].airdrop, Uint128::zero());
+    assert_eq!(nfts[1].airdrop, Uint128::new(100));
+    suite.airdrop(1).unwrap();
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[1].airdrop, Uint128::new(101));
+
+    suite
+        .execute(OWNER, ExecuteMsg::UpdateDenylist {
+            add_tokens: vec![],
+            remove_tokens: vec!["1".to_string()],
+            add_addresses: vec![],
+            remove_addresses: vec![Addr::unchecked(BOB)],
+        }, &[])
+        .unwrap();
+    let res: DenylistResponse = suite.query(&QueryMsg::Denylist {});
+    assert!(res.tokens.is_empty() && res.addresses.is_empty());
+}
+
+#[test]
+fn staking_caps_and_diminishing_weights() {
+    let mut suite = Suite::new();
+    let limits = |max_per_user, max_total_staked, weighting| ExecuteMsg::UpdateStakingLimits {
+        max_per_user,
+        max_total_staked,
+        weighting,
+    };
+    assert_error(suite.execute(ALICE, limits(Some(2), None, Weighting::Linear {}), &[]), ContractError::Unauthorized {});
+    suite.apply_change(limits(Some(2), Some(3), Weighting::Linear {})).unwrap();
+    let cfg = suite.config();
+    assert_eq!((cfg.max_per_user, cfg.max_total_staked), (Some(2), Some(3)));
+
+    let too_steep = Weighting::Diminishing { threshold: 1, factor: Decimal::percent(200) };
+    assert_error(suite.execute(OWNER, limits(None, None, too_steep), &[]), ContractError::InvalidWeighting {});
+    let diminishing = Weighting::Diminishing { threshold: 1, factor: Decimal::percent(50) };
+    suite.execute(OWNER, limits(None, None, diminishing.clone()), &[]).unwrap();
+    suite.advance(TIMELOCK_DELAY);
+
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    assert_error(suite.stake(ALICE, "3"), ContractError::MaxPerUserExceeded { max_per_user: 2 });
+    suite.stake(BOB, "5").unwrap();
+    assert_error(suite.stake(BOB, "6"), ContractError::MaxTotalStakedExceeded { max_total_staked: 3 });
+
+    suite.execute(OWNER, ExecuteMsg::ExecuteChange { id: 2 }, &[]).unwrap();
+    assert_eq!(suite.config().weighting, diminishing);
+
+    // Alice's second NFT counts half: weights 1, 0.5 and 1.
+    let res = suite.airdrop(250).unwrap();
+    let credits: Vec<(String, String)> = res.events
+        .iter()
+        .filter(|event| event.ty == "wasm-aliens_staking/airdrop_credit")
+        .map(|event| {
+            let value = |key: &str| event.attributes.iter().find(|attr| attr.key == key).unwrap().value.clone();
+            (value("token_id"), value("amount"))
+        })
+        .collect();
+    assert_eq!(
+        credits,
+        vec![("1".to_string(), "100".to_string()), ("2".to_string(), "50".to_string()), ("5".to_string(), "100".to_string())]
+    );
+    let alice = suite.staked_nfts(ALICE).nft_maps;
+    assert_eq!((alice[0].airdrop, alice[1].airdrop), (Uint128::new(100), Uint128::new(50)));
+    assert_eq!(suite.staked_nfts(BOB).nft_maps[0].airdrop, Uint128::new(100));
+}
+
+#[test]
+fn stake_age_and_time_weighted_shares() {
+    let mut suite = Suite::new();
+    let eligibility = |min_stake_age, time_weighted| ExecuteMsg::UpdateAirdropEligibility { min_stake_age, time_weighted };
+    assert_error(suite.execute(ALICE, eligibility(100, false), &[]), ContractError::Unauthorized {});
+    suite.execute(OWNER, eligibility(100, false), &[]).unwrap();
+    assert_eq!(suite.config().min_stake_age, 100);
+
+    let staked_at = suite.now();
+    suite.stake(ALICE, "1").unwrap();
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].staked_at, staked_at);
+    suite.advance(100);
+    suite.stake(BOB, "5").unwrap();
+    suite.advance(50);
+
+    // Bob's NFT is only 50 seconds old.
+    suite.airdrop(100).unwrap();
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].airdrop, Uint128::new(100));
+    assert_eq!(suite.staked_nfts(BOB).nft_maps[0].airdrop, Uint128::zero());
+
+    suite.execute(OWNER, eligibility(0, true), &[]).unwrap();
+    suite.execute(OWNER, ExecuteMsg::AirdropRestart {}, &[]).unwrap();
+    suite.advance(50);
+    suite.stake(BOB, "6").unwrap();
+    suite.advance(50);
+
+    // "6" was staked for half the epoch; the others for all of it.
+    suite.airdrop(250).unwrap();
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].airdrop, Uint128::new(200));
+    let bob = suite.staked_nfts(BOB).nft_maps;
+    assert_eq!((bob[0].airdrop, bob[1].airdrop), (Uint128::new(100), Uint128::new(50)));
+}
+
+#[test]
+fn restake_streaks() {
+    let mut suite = Suite::new();
+    let restake = |token_id: &str| ExecuteMsg::Restake { restake_nft_id: token_id.to_string(), owner: None };
+    let streaks = |suite: &Suite| -> (u32, Vec<u32>) {
+        let res: StreakResponse = suite.query(&QueryMsg::Streak { address: ALICE.to_string() });
+        (res.streak, res.nfts.iter().map(|nft| nft.streak).collect())
+    };
+
+    let zero_tier = vec![StreakTier { streak: 0, multiplier: Decimal::percent(200) }];
+    assert_error(
+        suite.execute(OWNER, ExecuteMsg::UpdateStreakTiers { tiers: zero_tier, renewal_window: 100 }, &[]),
+        ContractError::InvalidStreakTier {},
+    );
+    let tiers = vec![StreakTier { streak: 1, multiplier: Decimal::percent(200) }];
+    suite
+        .execute(OWNER, ExecuteMsg::UpdateStreakTiers { tiers: tiers.clone(), renewal_window: 100 }, &[])
+        .unwrap();
+    let res: StreakTiersResponse = suite.query(&QueryMsg::StreakTiers {});
+    assert_eq!((res.tiers, res.renewal_window), (tiers, 100));
+
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    suite.advance(DURATION + 10);
+    suite.execute(ALICE, restake("1"), &[]).unwrap();
+    assert_eq!(streaks(&suite), (1, vec![1, 0]));
+
+    // "2" misses its window, which breaks the account streak too.
+    suite.advance(100);
+    assert_eq!(streaks(&suite), (0, vec![1, 0]));
+    suite.execute(ALICE, restake("2"), &[]).unwrap();
+    assert_eq!(streaks(&suite), (0, vec![1, 0]));
+
+    suite.advance(DURATION);
+    suite.execute(ALICE, restake("1"), &[]).unwrap();
+    assert_eq!(streaks(&suite), (1, vec![2, 0]));
+
+    suite.advance(1);
+    suite.execute(ALICE, ExecuteMsg::Unstake { unstake_nft_id: "2".to_string() }, &[]).unwrap();
+    assert_eq!(streaks(&suite), (0, vec![2]));
+
+    // The streak tier doubles the weight of "1".
+    suite.stake(BOB, "5").unwrap();
+    suite.airdrop(300).unwrap();
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].airdrop, Uint128::new(200));
+    assert_eq!(suite.staked_nfts(BOB).nft_maps[0].airdrop, Uint128::new(100));
+}
+
+#[test]
+fn account_streak_rises_once_per_period() {
+    let mut suite = Suite::new();
+    let restake = |token_id: &str| ExecuteMsg::Restake { restake_nft_id: token_id.to_string(), owner: None };
+    let user_streak = |suite: &Suite| {
+        let res: StreakResponse = suite.query(&QueryMsg::Streak { address: ALICE.to_string() });
+        res.streak
+    };
+    suite
+        .execute(OWNER, ExecuteMsg::UpdateStreakTiers { tiers: vec![], renewal_window: 100 }, &[])
+        .unwrap();
+
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    suite.stake(ALICE, "3").unwrap();
+    suite.advance(DURATION + 10);
+    for token_id in ["1", "2", "3"] {
+        suite.execute(ALICE, restake(token_id), &[]).unwrap();
+    }
+    assert_eq!(user_streak(&suite), 1);
+
+    suite.advance(DURATION);
+    for token_id in ["1", "2", "3"] {
+        suite.execute(ALICE, restake(token_id), &[]).unwrap();
+    }
+    assert_eq!(user_streak(&suite), 2);
+}
+
+#[test]
+fn auto_renew_on_a_lapsed_lock_starts_over() {
+    let mut suite = Suite::new();
+    let auto_renew = |token_id: &str| ExecuteMsg::SetAutoRenew { token_ids: vec![token_id.to_string()], enabled: true };
+    let streaks = |suite: &Suite| -> (u32, Vec<u32>) {
+        let res: StreakResponse = suite.query(&QueryMsg::Streak { address: ALICE.to_string() });
+        (res.streak, res.nfts.iter().map(|nft| nft.streak).collect())
+    };
+    suite
+        .execute(OWNER, ExecuteMsg::UpdateStreakTiers { tiers: vec![], renewal_window: 100 }, &[])
+        .unwrap();
+
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    suite.execute(ALICE, auto_renew("2"), &[]).unwrap();
+    suite.advance(DURATION + 10);
+    suite
+        .execute(ALICE, ExecuteMsg::Restake { restake_nft_id: "1".to_string(), owner: None }, &[])
+        .unwrap();
+    assert_eq!(streaks(&suite), (1, vec![1, 1]));
+
+    // "1" lapses for 50 periods; switching auto-renew on does not count
+    // them as renewals, nor bring back the account streak.
+    suite.advance(50 * DURATION);
+    assert_eq!(streaks(&suite), (0, vec![0, 51]));
+    suite.execute(ALICE, auto_renew("1"), &[]).unwrap();
+    assert_eq!(streaks(&suite), (0, vec![0, 51]));
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].lock_time, suite.now() + DURATION);
+
+    suite.advance(DURATION + 1);
+    assert_eq!(streaks(&suite), (0, vec![1, 52]));
+}
+
+#[test]
+fn config_changes_wait_out_the_timelock() {
+    let mut suite = Suite::new();
+    let fee = ExecuteMsg::UpdateFeeAddress { fee_address: Addr::unchecked(BOB) };
+    assert_error(suite.execute(ALICE, fee.clone(), &[]), ContractError::Unauthorized {});
+    suite.execute(OWNER, fee, &[]).unwrap();
+    suite.execute(OWNER, ExecuteMsg::UpdateDuration { duration: 10 }, &[]).unwrap();
+
+    let eta = suite.now() + TIMELOCK_DELAY;
+    let res: PendingChangesResponse = suite.query(&QueryMsg::PendingChanges {});
+    assert_eq!(res.delay, TIMELOCK_DELAY);
+    assert_eq!(
+        res.changes,
+        vec![
+            PendingChangeResponse { id: 1, change: ConfigChange::FeeAddress { fee_address: Addr::unchecked(BOB) }, eta },
+            PendingChangeResponse { id: 2, change: ConfigChange::Duration { duration: 10 }, eta },
+        ]
+    );
+
+    assert_error(
+        suite.execute(OWNER, ExecuteMsg::ExecuteChange { id: 1 }, &[]),
+        ContractError::TimelockNotExpired { eta },
+    );
+    assert_error(suite.execute(ALICE, ExecuteMsg::CancelChange { id: 2 }, &[]), ContractError::Unauthorized {});
+    suite.execute(OWNER, ExecuteMsg::CancelChange { id: 2 }, &[]).unwrap();
+    assert_error(suite.execute(OWNER, ExecuteMsg::CancelChange { id: 2 }, &[]), ContractError::NoPendingChange {});
+
+    suite.advance(TIMELOCK_DELAY);
+    assert_error(suite.execute(ALICE, ExecuteMsg::ExecuteChange { id: 1 }, &[]), ContractError::Unauthorized {});
+    suite.execute(OWNER, ExecuteMsg::ExecuteChange { id: 1 }, &[]).unwrap();
+    assert_eq!(suite.config().fee_address, Addr::unchecked(BOB));
+    assert_eq!(suite.config().duration, DURATION);
+    assert_error(suite.execute(OWNER, ExecuteMsg::ExecuteChange { id: 2 }, &[]), ContractError::NoPendingChange {});
+
+    // The delay itself can only be changed through the timelock.
+    suite.apply_change(ExecuteMsg::UpdateTimelockDelay { delay: 10 }).unwrap();
+    let res: PendingChangesResponse = suite.query(&QueryMsg::PendingChanges {});
+    assert_eq!((res.changes, res.delay), (vec![], 10));
+}
+
+#[test]
+fn timelocked_owner_unbonding_and_config_diffs() {
+    let mut suite = Suite::new();
+
+    // Ownership moves through the timelock like the rest of the config.
+    suite.execute(OWNER, ExecuteMsg::UpdateOwner { owner: Addr::unchecked(BOB) }, &[]).unwrap();
+    assert_eq!(suite.config().owner, Addr::unchecked(OWNER));
+
+    // A queued config change only carries the fields it changes, so the fee
+    // address set in the meantime survives it.
+    let update = ExecuteMsg::UpdateConfig {
+        new_owner: Addr::unchecked(OWNER),
+        new_fee_address: Addr::unchecked(FEE_ADDRESS),
+        new_collection_address: suite.collection.clone(),
+        new_duration: 3 * DURATION,
+        new_locktime_fee: Uint128::new(LOCKTIME_FEE),
+    };
+    suite.execute(OWNER, update, &[]).unwrap();
+    let res: PendingChangesResponse = suite.query(&QueryMsg::PendingChanges {});
+    assert_eq!(
+        res.changes[1].change,
+        ConfigChange::Config {
+            new_owner: None,
+            new_fee_address: None,
+            new_collection_address: None,
+            new_duration: Some(3 * DURATION),
+            new_locktime_fee: None,
+        }
+    );
+    suite.execute(OWNER, ExecuteMsg::UpdateFeeAddress { fee_address: Addr::unchecked(ALICE) }, &[]).unwrap();
+
+    // Unbonding periods are bounded and wait out the delay too.
+    assert_error(
+        suite.execute(OWNER, ExecuteMsg::UpdateUnbondingPeriod { unbonding_period: u64::MAX }, &[]),
+        ContractError::InvalidUnbondingPeriod { max_unbonding_period: 365 * 86_400 },
+    );
+    suite.execute(OWNER, ExecuteMsg::UpdateUnbondingPeriod { unbonding_period: 1_000 }, &[]).unwrap();
+    let unbonding_period = |suite: &Suite| {
+        let res: NftClaimsResponse = suite.query(&QueryMsg::NftClaims { address: Addr::unchecked(ALICE) });
+        res.unbonding_period
+    };
+    assert_eq!(unbonding_period(&suite), 0);
+
+    suite.advance(TIMELOCK_DELAY);
+    for id in [3, 4, 2, 1] {
+        suite.execute(OWNER, ExecuteMsg::ExecuteChange { id }, &[]).unwrap();
+    }
+    let cfg = suite.config();
+    assert_eq!((cfg.owner, cfg.fee_address), (Addr::unchecked(BOB), Addr::unchecked(ALICE)));
+    assert_eq!((cfg.duration, unbonding_period(&suite)), (3 * DURATION, 1_000));
+}
+
+#[test]
+fn stakes_keep_the_terms_they_were_made_under() {
+    let mut suite = Suite::new();
+    let update = ExecuteMsg::UpdateConfig {
+        new_owner: Addr::unchecked(OWNER),
+        new_fee_address: Addr::unchecked(FEE_ADDRESS),
+        new_collection_address: suite.collection.clone(),
+        new_duration: 3 * DURATION,
+        new_locktime_fee: Uint128::new(500),
+    };
+    suite.execute(OWNER, update, &[]).unwrap();
+    suite.advance(TIMELOCK_DELAY - DURATION / 2);
+
+    suite.stake(ALICE, "1").unwrap();
+    let auto_renew = NftReceiveMsg::StakeV2 { lock_tier: None, auto_renew: Some(true), reward_recipient: None, beneficiary: None, receipt: None };
+    suite.send_nft(ALICE, "2", &auto_renew).unwrap();
+    let lock_time = suite.now() + DURATION;
+    suite.advance(DURATION / 2);
+    suite.execute(OWNER, ExecuteMsg::ExecuteChange { id: 1 }, &[]).unwrap();
+    suite.stake(BOB, "5").unwrap();
+
+    let alice = suite.staked_nfts(ALICE).nft_maps;
+    assert_eq!((alice[0].duration, alice[0].locktime_fee), (DURATION, Uint128::new(LOCKTIME_FEE)));
+    let bob = suite.staked_nfts(BOB).nft_maps;
+    assert_eq!((bob[0].duration, bob[0].locktime_fee), (3 * DURATION, Uint128::new(500)));
+
+    // The old fee still releases "1"; Bob pays the new one.
+    let unstake = |token_id: &str| ExecuteMsg::Unstake { unstake_nft_id: token_id.to_string() };
+    suite.execute(ALICE, unstake("1"), &coins(LOCKTIME_FEE, DENOM)).unwrap();
+    assert_error(suite.execute(BOB, unstake("5"), &coins(LOCKTIME_FEE, DENOM)), ContractError::Locktime {});
+
+    // Auto-renew keeps rolling "2" by the old duration.
+    suite.advance(DURATION / 2 + 1);
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].lock_time, lock_time + DURATION);
+}
+
+#[test]
+fn tiered_auto_renew_rolls_by_the_tier() {
+    let mut suite = Suite::new();
+    suite
+        .apply_change(ExecuteMsg::UpdateLockTiers {
+            tiers: vec![
+                LockTier { duration: DURATION, multiplier: Decimal::one() },
+                LockTier { duration: 3 * DURATION, multiplier: Decimal::percent(200) },
+            ],
+            max_lock_duration: 3 * DURATION,
+        })
+        .unwrap();
+    let tiered = NftReceiveMsg::StakeV2 { lock_tier: Some(1), auto_renew: Some(true), reward_recipient: None, beneficiary: None, receipt: None };
+    suite.send_nft(ALICE, "1", &tiered).unwrap();
+    let lock_time = suite.now() + 3 * DURATION;
+
+    let staked = &suite.staked_nfts(ALICE).nft_maps[0];
+    assert_eq!((staked.lock_time, staked.duration), (lock_time, 3 * DURATION));
+
+    suite.advance(3 * DURATION + 1);
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].lock_time, lock_time + 3 * DURATION);
+}
+
+#[test]
+fn receipt_holder_owns_the_position() {
+    let mut suite = Suite::new();
+    let with_receipt = NftReceiveMsg::StakeV2 {
+        lock_tier: None,
+        auto_renew: None,
+        reward_recipient: None,
+        beneficiary: None,
+        receipt: Some(true),
+    };
+    assert_error(suite.send_nft(ALICE, "1", &with_receipt), ContractError::NoReceiptCollection {});
+
+    let code_id = suite.app.store_code(cw721_contract());
+    let create = ExecuteMsg::CreateReceiptCollection {
+        code_id,
+        name: "Staked Aliens".to_string(),
+        symbol: "sALIEN".to_string(),
+    };
+    assert_error(suite.execute(ALICE, create.clone(), &[]), ContractError::Unauthorized {});
+    suite.execute(OWNER, create.clone(), &[]).unwrap();
+    assert_error(suite.execute(OWNER, create, &[]), ContractError::ReceiptCollectionExists {});
+    let receipts = suite.config().receipt_collection.unwrap();
+
+    suite.send_nft(ALICE, "1", &with_receipt).unwrap();
+    assert_eq!(suite.receipt_owner("1").unwrap(), ALICE);
+    assert!(suite.staked_nfts(ALICE).nft_maps[0].receipt);
+    suite.airdrop(100).unwrap();
+
+    // Selling the receipt hands over the pending reward and unstake rights.
+    suite
+        .app
+        .execute_contract(
+            Addr::unchecked(ALICE),
+            receipts.clone(),
+            &mock_cw721::ExecuteMsg::TransferNft { recipient: BOB.to_string(), token_id: "1".to_string() },
+            &[],
+        )
+        .unwrap();
+    let claim = ExecuteMsg::Claim { claim_nft_id: "1".to_string(), recipient: None, owner: None };
+    assert_error(suite.execute(ALICE, claim.clone(), &[]), ContractError::NoStakedNft {});
+    suite.execute(OWNER, ExecuteMsg::SyncReceipt { token_id: "1".to_string() }, &[]).unwrap();
+    assert!(suite.staked_nfts(ALICE).nft_maps.is_empty());
+    assert_eq!(suite.staked_nfts(BOB).nft_maps[0].airdrop, Uint128::new(100));
+    suite.execute(BOB, claim, &[]).unwrap();
+    assert_eq!(suite.balance(BOB), 10_100);
+
+    // Unstaking needs the receipt redeemed, which burns it.
+    suite.advance(DURATION + 1);
+    let unstake = ExecuteMsg::Unstake { unstake_nft_id: "1".to_string() };
+    assert_error(suite.execute(BOB, unstake.clone(), &[]), ContractError::ReceiptBacked {});
+    suite.redeem_receipt(BOB, "1").unwrap();
+    assert!(suite.receipt_owner("1").is_err());
+    assert!(!suite.staked_nfts(BOB).nft_maps[0].receipt);
+    suite.execute(BOB, unstake, &[]).unwrap();
+    assert_eq!(suite.nft_owner("1"), BOB);
+}
+
+#[test]
+fn receipts_do_not_lift_the_per_user_cap() {
+    let mut suite = Suite::new();
+    let receipts = suite.create_receipts();
+    let limits = ExecuteMsg::UpdateStakingLimits { max_per_user: Some(1), max_total_staked: None, weighting: Weighting::Linear {} };
+    suite.apply_change(limits).unwrap();
+    let with_receipt = NftReceiveMsg::StakeV2 {
+        lock_tier: None,
+        auto_renew: None,
+        reward_recipient: None,
+        beneficiary: None,
+        receipt: Some(true),
+    };
+    suite.send_nft(ALICE, "1", &with_receipt).unwrap();
+    suite.stake(BOB, "5").unwrap();
+    suite
+        .app
+        .execute_contract(
+            Addr::unchecked(ALICE),
+            receipts,
+            &mock_cw721::ExecuteMsg::TransferNft { recipient: BOB.to_string(), token_id: "1".to_string() },
+            &[],
+        )
+        .unwrap();
+
+    assert_error(suite.redeem_receipt(BOB, "1"), ContractError::MaxPerUserExceeded { max_per_user: 1 });
+
+    // A sync still hands Bob the position, but past the cap it earns nothing.
+    suite.execute(BOB, ExecuteMsg::SyncReceipt { token_id: "1".to_string() }, &[]).unwrap();
+    assert_eq!(suite.staked_nfts(BOB).nft_maps.len(), 2);
+    suite.airdrop(100).unwrap();
+    let bob = suite.staked_nfts(BOB).nft_maps;
+    assert_eq!((bob[0].airdrop, bob[1].airdrop), (Uint128::new(100), Uint128::zero()));
+}
+
+#[test]
+fn emergency_return_voids_receipts() {
+    let mut suite = Suite::new();
+    suite.create_receipts();
+    let with_receipt = NftReceiveMsg::StakeV2 {
+        lock_tier: None,
+        auto_renew: None,
+        reward_recipient: None,
+        beneficiary: None,
+        receipt: Some(true),
+    };
+    suite.send_nft(ALICE, "1", &with_receipt).unwrap();
+    suite.send_nft(ALICE, "2", &with_receipt).unwrap();
+
+    suite.execute(OWNER, ExecuteMsg::SetEmergency { enabled: true }, &[]).unwrap();
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::EmergencyWithdraw { token_ids: Some(vec!["1".to_string()]) }, &[]),
+        ContractError::ReceiptBacked {},
+    );
+    suite.execute(OWNER, ExecuteMsg::EmergencyReturnAll { limit: None }, &[]).unwrap();
+    assert_eq!(suite.nft_owner("1"), ALICE);
+    assert_eq!(suite.receipt_owner("1").unwrap(), ALICE);
+
+    // The void receipt keeps its id, so the next receipt gets a fresh one.
+    suite.execute(OWNER, ExecuteMsg::SetEmergency { enabled: false }, &[]).unwrap();
+    let res = suite.send_nft(ALICE, "1", &with_receipt).unwrap();
+    assert!(event_attributes(&res, "stake").contains(&("receipt_id".to_string(), "1.1".to_string())));
+    assert_eq!(suite.receipt_owner("1.1").unwrap(), ALICE);
+
+    let res = suite.redeem_receipt(ALICE, "1").unwrap();
+    assert_eq!(event_attributes(&res, "burn_void_receipt")[1], ("receipt_id".to_string(), "1".to_string()));
+    assert!(suite.receipt_owner("1").is_err());
+    assert!(suite.staked_nfts(ALICE).nft_maps[0].receipt);
+
+    suite.redeem_receipt(ALICE, "1.1").unwrap();
+    assert!(!suite.staked_nfts(ALICE).nft_maps[0].receipt);
+}
+
+#[test]
+fn transfer_stake_moves_position() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    suite.stake(ALICE, "3").unwrap();
+    suite.airdrop(300).unwrap();
+    let lock_time = suite.staked_nfts(ALICE).nft_maps[0].lock_time;
+    let transfer = |token_id: &str, to: &str, require_acceptance| ExecuteMsg::TransferStake {
+        token_id: token_id.to_string(),
+        to: Addr::unchecked(to),
+        require_acceptance,
+    };
+
+    assert_error(suite.execute(ALICE, transfer("1", ALICE, None), &[]), ContractError::InvalidTransfer {});
+    assert_error(suite.execute(ALICE, transfer("5", BOB, None), &[]), ContractError::NoStakedNft {});
+
+    // A direct transfer keeps the lock and the pending reward.
+    suite.advance(60);
+    suite.execute(ALICE, transfer("1", BOB, None), &[]).unwrap();
+    let moved = &suite.staked_nfts(BOB).nft_maps[0];
+    assert_eq!((moved.nft_id.as_str(), moved.lock_time, moved.airdrop), ("1", lock_time, Uint128::new(100)));
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps.len(), 2);
+
+    // With acceptance the position stays put until the recipient accepts.
+    suite.execute(ALICE, transfer("2", BOB, Some(true)), &[]).unwrap();
+    let pending: PendingTransferResponse = suite.query(&QueryMsg::PendingTransfer { token_id: "2".to_string() });
+    assert_eq!(pending.transfer.unwrap().to, Addr::unchecked(BOB));
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps.len(), 2);
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::AcceptStake { token_id: "2".to_string() }, &[]),
+        ContractError::Unauthorized {},
+    );
+    suite.execute(BOB, ExecuteMsg::AcceptStake { token_id: "2".to_string() }, &[]).unwrap();
+    assert_eq!(suite.staked_nfts(BOB).nft_maps.len(), 2);
+
+    suite.execute(ALICE, transfer("3", BOB, Some(true)), &[]).unwrap();
+    suite.execute(BOB, ExecuteMsg::CancelStakeTransfer { token_id: "3".to_string() }, &[]).unwrap();
+    assert_error(
+        suite.execute(BOB, ExecuteMsg::AcceptStake { token_id: "3".to_string() }, &[]),
+        ContractError::NoPendingTransfer {},
+    );
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].nft_id, "3");
+
+    // The new owner unstakes and receives the NFT.
+    suite.advance(DURATION);
+    suite.execute(BOB, ExecuteMsg::Unstake { unstake_nft_id: "1".to_string() }, &[]).unwrap();
+    assert_eq!(suite.nft_owner("1"), BOB);
+}
+
+#[test]
+fn transfer_stake_checks_recipient_and_notifies_hooks() {
+    let mut suite = Suite::new();
+    let hook_id = suite.app.store_code(hook_contract());
+    let hook = suite
+        .app
+        .instantiate_contract(hook_id, Addr::unchecked(OWNER), &Empty {}, &[], "hook", None)
+        .unwrap();
+    suite.execute(OWNER, ExecuteMsg::AddHook { addr: hook.clone() }, &[]).unwrap();
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    suite.stake(BOB, "5").unwrap();
+    let transfer = |token_id: &str, require_acceptance| ExecuteMsg::TransferStake {
+        token_id: token_id.to_string(),
+        to: Addr::unchecked(BOB),
+        require_acceptance,
+    };
+
+    // Bob is at the per-user cap.
+    let limits = ExecuteMsg::UpdateStakingLimits { max_per_user: Some(1), max_total_staked: None, weighting: Weighting::Linear {} };
+    suite.apply_change(limits).unwrap();
+    assert_error(suite.execute(ALICE, transfer("1", None), &[]), ContractError::MaxPerUserExceeded { max_per_user: 1 });
+    let limits = ExecuteMsg::UpdateStakingLimits { max_per_user: None, max_total_staked: None, weighting: Weighting::Linear {} };
+    suite.apply_change(limits).unwrap();
+
+    // A denylisted recipient cannot take a position, even one offered before.
+    suite.execute(ALICE, transfer("2", Some(true)), &[]).unwrap();
+    let deny = |add: Vec<Addr>, remove: Vec<Addr>| ExecuteMsg::UpdateDenylist {
+        add_tokens: vec![],
+        remove_tokens: vec![],
+        add_addresses: add,
+        remove_addresses: remove,
+    };
+    suite.execute(OWNER, deny(vec![Addr::unchecked(BOB)], vec![]), &[]).unwrap();
+    assert_error(suite.execute(ALICE, transfer("1", None), &[]), ContractError::DeniedAddress {});
+    assert_error(
+        suite.execute(BOB, ExecuteMsg::AcceptStake { token_id: "2".to_string() }, &[]),
+        ContractError::DeniedAddress {},
+    );
+    suite.execute(OWNER, deny(vec![], vec![Addr::unchecked(BOB)]), &[]).unwrap();
+
+    // Moves are held back by the stake and unstake pauses.
+    suite.execute(OWNER, ExecuteMsg::UpdatePauser { pauser: Some(Addr::unchecked(OWNER)) }, &[]).unwrap();
+    let paused = PauseFlags { unstake: true, ..PauseFlags::default() };
+    suite.execute(OWNER, ExecuteMsg::UpdatePauseFlags { flags: paused }, &[]).unwrap();
+    assert_error(
+        suite.execute(ALICE, transfer("1", None), &[]),
+        ContractError::Paused { operation: "unstake".to_string() },
+    );
+    let paused = PauseFlags { stake: true, ..PauseFlags::default() };
+    suite.execute(OWNER, ExecuteMsg::UpdatePauseFlags { flags: paused }, &[]).unwrap();
+    assert_error(
+        suite.execute(BOB, ExecuteMsg::AcceptStake { token_id: "2".to_string() }, &[]),
+        ContractError::Paused { operation: "stake".to_string() },
+    );
+    suite.execute(OWNER, ExecuteMsg::UpdatePauseFlags { flags: PauseFlags::default() }, &[]).unwrap();
+
+    suite.execute(ALICE, transfer("1", None), &[]).unwrap();
+    suite.execute(BOB, ExecuteMsg::AcceptStake { token_id: "2".to_string() }, &[]).unwrap();
+
+    let events: Vec<StakeChangedHookMsg> = suite.app.wrap().query_wasm_smart(&hook, &Empty {}).unwrap();
+    let summary: Vec<_> = events[3..].iter().map(|e| (e.kind.clone(), e.user.as_str(), e.token_id.as_str(), e.count)).collect();
+    assert_eq!(
+        summary,
+        vec![
+            (StakeChangeKind::Unstake, ALICE, "1", 1),
+            (StakeChangeKind::Stake, BOB, "1", 2),
+            (StakeChangeKind::Unstake, ALICE, "2", 0),
+            (StakeChangeKind::Stake, BOB, "2", 3),
+        ]
+    );
+}
+
+#[test]
+fn stake_requires_open_window_and_enabled_contract() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+    suite.airdrop(100).unwrap();
+    assert_error(suite.stake(ALICE, "2"), ContractError::NotStarted {});
+
+    suite.execute(OWNER, ExecuteMsg::AirdropRestart {}, &[]).unwrap();
+    suite.execute(OWNER, ExecuteMsg::UpdateEnabled { enabled: false }, &[]).unwrap();
+    assert_error(suite.stake(ALICE, "2"), ContractError::Disabled {});
+}
+
+#[test]
+fn locked_unstake_charges_fee() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+
+    let unstake = ExecuteMsg::Unstake { unstake_nft_id: "1".to_string() };
+    assert_error(suite.execute(ALICE, unstake.clone(), &[]), ContractError::Locktime {});
+    assert_error(
+        suite.execute(ALICE, unstake.clone(), &coins(LOCKTIME_FEE - 1, DENOM)),
+        ContractError::Locktime {},
+    );
+
+    suite.execute(ALICE, unstake, &coins(LOCKTIME_FEE, DENOM)).unwrap();
+    assert_eq!(suite.nft_owner("1"), ALICE);
+    assert_eq!(suite.balance(FEE_ADDRESS), LOCKTIME_FEE);
+    assert_eq!(suite.balance(ALICE), 10_000 - LOCKTIME_FEE);
+    assert!(suite.staked_nfts(ALICE).nft_maps.is_empty());
+}
+
+#[test]
+fn unstake_after_expiry_is_free() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+    suite.advance(DURATION);
+
+    // A fee sent after the lock has ended is refunded.
+    suite
+        .execute(ALICE, ExecuteMsg::Unstake { unstake_nft_id: "1".to_string() }, &coins(LOCKTIME_FEE, DENOM))
+        .unwrap();
+    assert_eq!(suite.nft_owner("1"), ALICE);
+    assert_eq!(suite.balance(FEE_ADDRESS), 0);
+    assert_eq!(suite.balance(ALICE), 10_000);
+    assert_eq!(suite.config().total_staked, 0);
+
+    // Funds in another denom are rejected rather than kept.
+    suite.stake(ALICE, "2").unwrap();
+    suite.advance(DURATION);
+    let unstake = ExecuteMsg::Unstake { unstake_nft_id: "2".to_string() };
+    assert_error(
+        suite.execute(ALICE, unstake.clone(), &coins(1, OTHER_DENOM)),
+        ContractError::Payment(cw_utils::PaymentError::ExtraDenom(OTHER_DENOM.to_string())),
+    );
+    assert_error(
+        suite.execute(ALICE, unstake, &[coin(1, DENOM), coin(1, OTHER_DENOM)]),
+        ContractError::Payment(cw_utils::PaymentError::ExtraDenom(OTHER_DENOM.to_string())),
+    );
+}
+
+#[test]
+fn unstake_unknown_token() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::Unstake { unstake_nft_id: "2".to_string() }, &[]),
+        ContractError::NoStakedNft {},
+    );
+}
+
+#[test]
+fn restake_only_after_expiry() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+
+    let restake = ExecuteMsg::Restake { restake_nft_id: "1".to_string(), owner: None };
+    assert_error(suite.execute(ALICE, restake.clone(), &[]), ContractError::Locktime {});
+
+    suite.advance(DURATION + 10);
+    suite.execute(ALICE, restake, &[]).unwrap();
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].lock_time, suite.now() + DURATION);
+}
+
+#[test]
+fn airdrop_split_and_claim() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    suite.stake(BOB, "5").unwrap();
+
+    suite.airdrop(300).unwrap();
+    for nft in suite.staked_nfts(ALICE).nft_maps.iter().chain(suite.staked_nfts(BOB).nft_maps.iter()) {
+        assert_eq!(nft.airdrop, Uint128::new(100));
+    }
+    assert_eq!(suite.config().total_airdrop, Uint128::new(300));
+    assert!(!suite.config().start_airdrop);
+
+    suite
+        .execute(ALICE, ExecuteMsg::Claim { claim_nft_id: "1".to_string(), recipient: None, owner: None }, &[])
+        .unwrap();
+    assert_eq!(suite.balance(ALICE), 10_100);
+    let earned: TotalEarnedResponse = suite.query(&QueryMsg::GetTotalEarned { address: Addr::unchecked(ALICE) });
+    assert_eq!(earned.total_earned, Uint128::new(100));
+
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::Claim { claim_nft_id: "1".to_string(), recipient: None, owner: None }, &[]),
+        ContractError::NoReward {},
+    );
+}
+
+#[test]
+fn handlers_emit_structured_events() {
+    let mut suite = Suite::new();
+    let collection = suite.collection.to_string();
+    let attrs = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    };
+
+    let res = suite.stake(ALICE, "1").unwrap();
+    let lock_time = (suite.now() + DURATION).to_string();
+    assert_eq!(
+        event_attributes(&res, "stake"),
+        attrs(&[
+            ("user", ALICE),
+            ("collection", &collection),
+            ("token_id", "1"),
+            ("lock_time", &lock_time),
+            ("auto_renew", "false"),
+            ("receipt", "false"),
+            ("duration", &DURATION.to_string()),
+            ("locktime_fee", &LOCKTIME_FEE.to_string()),
+            ("sender", ALICE),
+        ])
+    );
+
+    let res = suite.airdrop(100).unwrap();
+    let airdrop = event_attributes(&res, "airdrop");
+    assert!(airdrop.contains(&("amount".to_string(), "100".to_string())));
+    assert!(airdrop.contains(&("denom".to_string(), DENOM.to_string())));
+
+    let res = suite
+        .execute(ALICE, ExecuteMsg::Claim { claim_nft_id: "1".to_string(), recipient: None, owner: None }, &[])
+        .unwrap();
+    assert_eq!(
+        event_attributes(&res, "claim")[8..],
+        attrs(&[("sender", ALICE), ("recipient", ALICE), ("amount", "100"), ("denom", DENOM), ("mode", "send")])
+    );
+
+    let res = suite
+        .execute(ALICE, ExecuteMsg::Unstake { unstake_nft_id: "1".to_string() }, &coins(LOCKTIME_FEE + 5, DENOM))
+        .unwrap();
+    let release_at = suite.now().to_string();
+    assert_eq!(
+        event_attributes(&res, "unstake"),
+        attrs(&[
+            ("user", ALICE),
+            ("collection", &collection),
+            ("token_id", "1"),
+            ("lock_time", &lock_time),
+            ("auto_renew", "false"),
+            ("receipt", "false"),
+            ("duration", &DURATION.to_string()),
+            ("locktime_fee", &LOCKTIME_FEE.to_string()),
+            ("fee", "100"),
+            ("refund", "5"),
+            ("denom", DENOM),
+            ("forfeited", "0"),
+            ("release_at", &release_at),
+        ])
+    );
+
+    let res = suite.apply_change(ExecuteMsg::UpdateDuration { duration: 10 }).unwrap();
+    assert_eq!(event_attributes(&res, "update_duration"), attrs(&[("duration", "10")]));
+}
+
+#[test]
+fn airdrop_carries_rounding_dust() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    suite.stake(BOB, "5").unwrap();
+
+    let response = suite.airdrop(302).unwrap();
+    assert!(response.events.iter().any(|event| event
+        .attributes
+        .iter()
+        .any(|attr| attr.key == "remainder" && attr.value == "2")));
+    assert_eq!(suite.config().total_airdrop, Uint128::new(300));
+    assert_eq!(suite.config().airdrop_dust, Uint128::new(2));
+
+    suite.execute(OWNER, ExecuteMsg::AirdropRestart {}, &[]).unwrap();
+    suite.airdrop(298).unwrap();
+    assert_eq!(suite.staked_nfts(BOB).nft_maps[0].airdrop, Uint128::new(200));
+    assert_eq!(suite.config().total_airdrop, Uint128::new(600));
+    assert_eq!(suite.config().airdrop_dust, Uint128::zero());
+}
+
+#[test]
+fn sponsored_airdrop_distributes_attached_funds() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(BOB, "5").unwrap();
+
+    assert_error(
+        suite.execute(BOB, ExecuteMsg::FundAirdrop {}, &[]),
+        ContractError::Payment(cw_utils::PaymentError::NoFunds {}),
+    );
+
+    suite.execute(BOB, ExecuteMsg::FundAirdrop {}, &coins(200, DENOM)).unwrap();
+    assert_eq!(suite.balance(BOB), 9_800);
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].airdrop, Uint128::new(100));
+    // Sponsoring does not close the staking window.
+    assert!(suite.config().start_airdrop);
+
+    let sponsored: SponsoredResponse = suite.query(&QueryMsg::Sponsored { address: Addr::unchecked(BOB) });
+    assert_eq!(sponsored.amount, Uint128::new(200));
+}
+
+#[test]
+fn claim_to_recipient_and_through_operator() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    suite.airdrop(200).unwrap();
+
+    let claim = |token: &str, recipient: Option<&str>, owner: Option<&str>| ExecuteMsg::Claim {
+        claim_nft_id: token.to_string(),
+        recipient: recipient.map(Addr::unchecked),
+        owner: owner.map(Addr::unchecked),
+    };
+
+    suite
+        .execute(ALICE, ExecuteMsg::SetRewardRecipient { recipient: Some(Addr::unchecked("cold")) }, &[])
+        .unwrap();
+    assert_error(suite.execute(BOB, claim("1", None, Some(ALICE)), &[]), ContractError::Unauthorized {});
+
+    suite
+        .execute(ALICE, ExecuteMsg::ApproveAll { operator: Addr::unchecked(BOB), expires: None }, &[])
+        .unwrap();
+    let operator: OperatorResponse = suite.query(&QueryMsg::Operator {
+        owner: Addr::unchecked(ALICE),
+        operator: Addr::unchecked(BOB),
+    });
+    assert!(operator.approved);
+
+    assert_error(suite.execute(BOB, claim("1", Some(BOB), Some(ALICE)), &[]), ContractError::Unauthorized {});
+    suite.execute(BOB, claim("1", None, Some(ALICE)), &[]).unwrap();
+    assert_eq!(suite.balance("cold"), 100);
+    assert_eq!(suite.balance(BOB), 10_000);
+
+    assert!(suite.execute(ALICE, claim("2", Some("Hot"), None), &[]).is_err());
+    suite.execute(ALICE, claim("2", Some("hot"), None), &[]).unwrap();
+    assert_eq!(suite.balance("hot"), 100);
+
+    suite
+        .execute(ALICE, ExecuteMsg::RevokeAll { operator: Addr::unchecked(BOB) }, &[])
+        .unwrap();
+    suite.execute(OWNER, ExecuteMsg::AirdropRestart {}, &[]).unwrap();
+    let restake = ExecuteMsg::Restake { restake_nft_id: "1".to_string(), owner: Some(Addr::unchecked(ALICE)) };
+    assert_error(suite.execute(BOB, restake, &[]), ContractError::Unauthorized {});
+
+    let expired = cw_utils::Expiration::AtHeight(suite.app.block_info().height);
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::ApproveAll { operator: Addr::unchecked(BOB), expires: Some(expired) }, &[]),
+        ContractError::Expired {},
+    );
+}
+
+#[test]
+fn claim_compounds_into_vault() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+    suite.airdrop(100).unwrap();
+
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::SetClaimMode { mode: ClaimMode::Vault {} }, &[]),
+        ContractError::NoVault {},
+    );
+
+    let vault_id = suite.app.store_code(vault_contract());
+    let vault = suite
+        .app
+        .instantiate_contract(vault_id, Addr::unchecked(OWNER), &Empty {}, &[], "vault", None)
+        .unwrap();
+    // The vault only takes over once the timelock has passed.
+    suite.execute(OWNER, ExecuteMsg::UpdateVault { vault: Some(vault.clone()) }, &[]).unwrap();
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::SetClaimMode { mode: ClaimMode::Vault {} }, &[]),
+        ContractError::NoVault {},
+    );
+    let res: PendingChangesResponse = suite.query(&QueryMsg::PendingChanges {});
+    assert_eq!(res.changes[0].change, ConfigChange::Vault { vault: Some(vault.clone()) });
+    suite.advance(TIMELOCK_DELAY);
+    suite.execute(OWNER, ExecuteMsg::ExecuteChange { id: res.changes[0].id }, &[]).unwrap();
+    suite
+        .execute(ALICE, ExecuteMsg::SetClaimMode { mode: ClaimMode::Vault {} }, &[])
+        .unwrap();
+
+    suite
+        .execute(ALICE, ExecuteMsg::Claim { claim_nft_id: "1".to_string(), recipient: None, owner: None }, &[])
+        .unwrap();
+    assert_eq!(suite.balance(vault.as_str()), 100);
+    assert_eq!(suite.balance(ALICE), 10_000);
+
+    let delegations: DelegationsResponse = suite.query(&QueryMsg::Delegations { address: Addr::unchecked(ALICE) });
+    assert_eq!(delegations.claim_mode, ClaimMode::Vault {});
+    assert!(delegations.delegations.is_empty());
+
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::Undelegate { validator: "validator".to_string(), amount: Uint128::new(1) }, &[]),
+        ContractError::InsufficientDelegation {},
+    );
+    assert_error(suite.execute(ALICE, ExecuteMsg::ClaimUndelegated {}, &[]), ContractError::NoReward {});
+}
+
+#[test]
+fn airdrop_skips_expired_locks() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+    suite.advance(DURATION / 2);
+    suite.stake(BOB, "5").unwrap();
+    suite.advance(DURATION / 2);
+
+    suite.airdrop(100).unwrap();
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].airdrop, Uint128::zero());
+    assert_eq!(suite.staked_nfts(BOB).nft_maps[0].airdrop, Uint128::new(100));
+
+    suite.advance(DURATION);
+    assert_error(suite.airdrop(100), ContractError::NoUnexpiredNft {});
+}
+
+#[test]
+fn airdrop_validation() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::Airdrop { airdrop_amount: Uint128::new(10) }, &[]),
+        ContractError::Unauthorized {},
+    );
+    assert_error(
+        suite.execute(OWNER, ExecuteMsg::Airdrop { airdrop_amount: Uint128::zero() }, &[]),
+        ContractError::InvalidAirdrop {},
+    );
+    assert_error(
+        suite.execute(OWNER, ExecuteMsg::Airdrop { airdrop_amount: Uint128::new(10) }, &[]),
+        ContractError::InsufficientCw20 {},
+    );
+    assert_error(suite.airdrop(1), ContractError::OverNftCount { nft_count: Uint128::new(2) });
+}
+
+#[test]
+fn owner_cannot_withdraw_owed_rewards() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    suite.stake(BOB, "5").unwrap();
+    suite.airdrop(302).unwrap();
+
+    // 300 is owed to stakers and 2 is carried as dust.
+    assert_error(
+        suite.execute(OWNER, ExecuteMsg::Withdraw { amount: Uint128::new(1) }, &[]),
+        ContractError::InsufficientCw20 {},
+    );
+    suite.execute(OWNER, ExecuteMsg::AirdropRestart {}, &[]).unwrap();
+    assert_error(
+        suite.execute(OWNER, ExecuteMsg::Airdrop { airdrop_amount: Uint128::new(3) }, &[]),
+        ContractError::InsufficientCw20 {},
+    );
+
+    // Claimed rewards leave the reserve; forfeited ones return to the owner.
+    suite.execute(BOB, ExecuteMsg::Claim { claim_nft_id: "5".to_string(), recipient: None, owner: None }, &[]).unwrap();
+    suite.advance(DURATION);
+    suite.execute(ALICE, ExecuteMsg::Unstake { unstake_nft_id: "1".to_string() }, &[]).unwrap();
+    assert_error(
+        suite.execute(OWNER, ExecuteMsg::Withdraw { amount: Uint128::new(101) }, &[]),
+        ContractError::InsufficientCw20 {},
+    );
+    suite.execute(OWNER, ExecuteMsg::Withdraw { amount: Uint128::new(100) }, &[]).unwrap();
+    assert_eq!(suite.balance(suite.staking.as_str()), 102);
+    assert_eq!(suite.config().unclaimed_rewards, Uint128::new(100));
+}
+
+#[test]
+fn owner_only_updates() {
+    let mut suite = Suite::new();
+
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::UpdateOwner { owner: Addr::unchecked(ALICE) }, &[]),
+        ContractError::Unauthorized {},
+    );
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::Withdraw { amount: Uint128::new(1) }, &[]),
+        ContractError::Unauthorized {},
+    );
+    assert_error(
+        suite.execute(OWNER, ExecuteMsg::Withdraw { amount: Uint128::new(1) }, &[]),
+        ContractError::InsufficientCw20 {},
+    );
+
+    suite.apply_change(ExecuteMsg::UpdateDuration { duration: 100 }).unwrap();
+    assert_eq!(suite.config().duration, 100);
+}
+
+#[test]
+fn auto_renew_keeps_earning() {
+    let mut suite = Suite::new();
+    suite
+        .send_nft(ALICE, "1", &NftReceiveMsg::Stake {
+            sender: ALICE.to_string(),
+            token_id: "1".to_string(),
+            auto_renew: Some(true),
+        })
+        .unwrap();
+    let staked_at = suite.now();
+
+    suite.advance(DURATION + 10);
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].lock_time, staked_at + 2 * DURATION);
+    suite.airdrop(100).unwrap();
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].airdrop, Uint128::new(100));
+
+    suite
+        .execute(ALICE, ExecuteMsg::SetAutoRenew { token_ids: vec!["1".to_string()], enabled: false }, &[])
+        .unwrap();
+    suite.advance(DURATION);
+    suite
+        .execute(ALICE, ExecuteMsg::Unstake { unstake_nft_id: "1".to_string() }, &[])
+        .unwrap();
+    assert_eq!(suite.nft_owner("1"), ALICE);
+}
+
+#[test]
+fn extend_lock_up_to_max() {
+    let mut suite = Suite::new();
+    suite
+        .apply_change(ExecuteMsg::UpdateLockTiers {
+            tiers: vec![
+                LockTier { duration: DURATION, multiplier: Decimal::one() },
+                LockTier { duration: 3 * DURATION, multiplier: Decimal::percent(200) },
+            ],
+            max_lock_duration: 3 * DURATION,
+        })
+        .unwrap();
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(BOB, "5").unwrap();
+
+    let extend = |additional_seconds, tier| ExecuteMsg::ExtendLock {
+        token_id: "1".to_string(),
+        additional_seconds,
+        tier,
+        owner: None,
+    };
+    assert_error(suite.execute(ALICE, extend(None, None), &[]), ContractError::InvalidExtension {});
+    assert_error(suite.execute(ALICE, extend(Some(1), Some(0)), &[]), ContractError::InvalidExtension {});
+    assert_error(suite.execute(ALICE, extend(None, Some(2)), &[]), ContractError::InvalidTier {});
+    assert_error(suite.execute(ALICE, extend(Some(u64::MAX), None), &[]), ContractError::InvalidExtension {});
+    assert_error(
+        suite.execute(ALICE, extend(Some(2 * DURATION + 1), None), &[]),
+        ContractError::OverMaxLock { max_lock_duration: 3 * DURATION },
+    );
+
+    suite.execute(ALICE, extend(None, Some(1)), &[]).unwrap();
+    let extended = &suite.staked_nfts(ALICE).nft_maps[0];
+    assert_eq!((extended.lock_time, extended.duration), (suite.now() + 3 * DURATION, 3 * DURATION));
+
+    // The three-period lock weighs twice the one-period lock, however much
+    // of it is left.
+    suite.advance(DURATION / 2);
+    suite.airdrop(300).unwrap();
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].airdrop, Uint128::new(200));
+    assert_eq!(suite.staked_nfts(BOB).nft_maps[0].airdrop, Uint128::new(100));
+}
+
+#[test]
+fn tiered_stake_keeps_its_weight() {
+    let mut suite = Suite::new();
+    suite
+        .apply_change(ExecuteMsg::UpdateLockTiers {
+            tiers: vec![
+                LockTier { duration: DURATION, multiplier: Decimal::one() },
+                LockTier { duration: 3 * DURATION, multiplier: Decimal::percent(200) },
+            ],
+            max_lock_duration: 3 * DURATION,
+        })
+        .unwrap();
+    let tiered = NftReceiveMsg::StakeV2 { lock_tier: Some(1), auto_renew: None, reward_recipient: None, beneficiary: None, receipt: None };
+    suite.send_nft(ALICE, "1", &tiered).unwrap();
+    suite.stake(BOB, "5").unwrap();
+
+    suite.advance(1);
+    suite.airdrop(300).unwrap();
+    assert_eq!(suite.staked_nfts(ALICE).nft_maps[0].airdrop, Uint128::new(200));
+    assert_eq!(suite.staked_nfts(BOB).nft_maps[0].airdrop, Uint128::new(100));
+}
+
+#[test]
+fn voting_power_snapshots() {
+    let mut suite = Suite::new();
+    suite
+        .apply_change(ExecuteMsg::UpdateLockTiers {
+            tiers: vec![
+                LockTier { duration: DURATION, multiplier: Decimal::one() },
+                LockTier { duration: 3 * DURATION, multiplier: Decimal::percent(200) },
+            ],
+            max_lock_duration: 3 * DURATION,
+        })
+        .unwrap();
+    let power = |suite: &Suite, address: &str, height: Option<u64>| {
+        let res: VotingPowerAtHeightResponse =
+            suite.query(&QueryMsg::VotingPowerAtHeight { address: address.to_string(), height });
+        res.power.u128()
+    };
+    let total = |suite: &Suite, height: Option<u64>| {
+        let res: TotalPowerAtHeightResponse = suite.query(&QueryMsg::TotalPowerAtHeight { height });
+        res.power.u128()
+    };
+
+    let staked_at = suite.app.block_info().height;
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(BOB, "5").unwrap();
+    assert_eq!(power(&suite, ALICE, None), 1_000_000);
+    assert_eq!(total(&suite, None), 2_000_000);
+
+    suite.advance(60);
+    let extended_at = suite.app.block_info().height;
+    suite
+        .execute(ALICE, ExecuteMsg::ExtendLock {
+            token_id: "1".to_string(),
+            additional_seconds: None,
+            tier: Some(1),
+            owner: None,
+        }, &[])
+        .unwrap();
+    assert_eq!(power(&suite, ALICE, None), 2_000_000);
+    assert_eq!(total(&suite, None), 3_000_000);
+
+    // Snapshots answer with the power held when the block started.
+    assert_eq!(power(&suite, ALICE, Some(staked_at)), 0);
+    assert_eq!(power(&suite, ALICE, Some(extended_at)), 1_000_000);
+    assert_eq!(total(&suite, Some(extended_at)), 2_000_000);
+    assert_eq!(total(&suite, Some(staked_at)), 0);
+
+    suite.advance(DURATION);
+    suite.execute(BOB, ExecuteMsg::Unstake { unstake_nft_id: "5".to_string() }, &[]).unwrap();
+    assert_eq!(power(&suite, BOB, None), 0);
+    assert_eq!(total(&suite, None), 2_000_000);
+
+    // Power lapses with the lock once anyone syncs the account, and keeps
+    // the multiplier of the last stake, restake or extension whatever the
+    // tiers are now.
+    suite.advance(3 * DURATION);
+    suite
+        .apply_change(ExecuteMsg::UpdateLockTiers {
+            tiers: vec![LockTier { duration: DURATION, multiplier: Decimal::percent(300) }],
+            max_lock_duration: 3 * DURATION,
+        })
+        .unwrap();
+    assert_eq!(power(&suite, ALICE, None), 2_000_000);
+    let sync = ExecuteMsg::SyncVotingPower { addresses: vec![Addr::unchecked(ALICE)] };
+    let res = suite.execute(BOB, sync, &[]).unwrap();
+    assert_eq!(event_attributes(&res, "sync_voting_power")[1], ("power".to_string(), "0".to_string()));
+    assert_eq!(total(&suite, None), 0);
+    suite.execute(ALICE, ExecuteMsg::SetAutoRenew { token_ids: vec!["1".to_string()], enabled: true }, &[]).unwrap();
+    assert_eq!(power(&suite, ALICE, None), 2_000_000);
+    suite.stake(BOB, "6").unwrap();
+    assert_eq!(power(&suite, BOB, None), 3_000_000);
+    assert_eq!(total(&suite, None), 5_000_000);
+
+    // Moving to another collection drops every position's power at once.
+    suite
+        .apply_change(ExecuteMsg::UpdateConfig {
+            new_owner: Addr::unchecked(OWNER),
+            new_fee_address: Addr::unchecked(FEE_ADDRESS),
+            new_collection_address: Addr::unchecked("other_collection"),
+            new_duration: DURATION,
+            new_locktime_fee: Uint128::new(LOCKTIME_FEE),
+        })
+        .unwrap();
+    assert_eq!(power(&suite, ALICE, None), 0);
+    assert_eq!(total(&suite, None), 0);
+
+    let info: InfoResponse = suite.query(&QueryMsg::Info {});
+    assert_eq!(info.info.contract, "crates.io:staking");
+}
+
+#[test]
+fn invalid_lock_tiers() {
+    let mut suite = Suite::new();
+    assert_error(
+        suite.execute(OWNER, ExecuteMsg::UpdateLockTiers {
+            tiers: vec![LockTier { duration: DURATION, multiplier: Decimal::zero() }],
+            max_lock_duration: DURATION,
+        }, &[]),
+        ContractError::InvalidTier {},
+    );
+}
+
+#[test]
+fn unbonding_unstake_and_withdraw() {
+    let mut suite = Suite::new();
+    suite
+        .apply_change(ExecuteMsg::UpdateUnbondingPeriod { unbonding_period: 1_000 })
+        .unwrap();
+    suite.stake(ALICE, "1").unwrap();
+    suite.advance(DURATION);
+
+    suite
+        .execute(ALICE, ExecuteMsg::Unstake { unstake_nft_id: "1".to_string() }, &[])
+        .unwrap();
+    assert_eq!(suite.nft_owner("1"), suite.staking.to_string());
+    let claims: NftClaimsResponse = suite.query(&QueryMsg::NftClaims { address: Addr::unchecked(ALICE) });
+    assert_eq!(claims.claims.len(), 1);
+    assert_eq!(claims.claims[0].release_at, suite.now() + 1_000);
+
+    assert_error(suite.execute(ALICE, ExecuteMsg::WithdrawNft {}, &[]), ContractError::NoMaturedClaims {});
+    suite.advance(1_000);
+    suite.execute(ALICE, ExecuteMsg::WithdrawNft {}, &[]).unwrap();
+    assert_eq!(suite.nft_owner("1"), ALICE);
+    let claims: NftClaimsResponse = suite.query(&QueryMsg::NftClaims { address: Addr::unchecked(ALICE) });
+    assert!(claims.claims.is_empty());
+}
+
+#[test]
+fn emergency_returns_nfts() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    suite.stake(BOB, "5").unwrap();
+    suite.airdrop(300).unwrap();
+
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::EmergencyWithdraw { token_ids: None }, &[]),
+        ContractError::NotEmergency {},
+    );
+
+    suite.execute(OWNER, ExecuteMsg::UpdateEnabled { enabled: false }, &[]).unwrap();
+    suite.execute(OWNER, ExecuteMsg::SetEmergency { enabled: true }, &[]).unwrap();
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::Claim { claim_nft_id: "1".to_string(), recipient: None, owner: None }, &[]),
+        ContractError::Disabled {},
+    );
+    suite.execute(OWNER, ExecuteMsg::UpdateEnabled { enabled: true }, &[]).unwrap();
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::Claim { claim_nft_id: "1".to_string(), recipient: None, owner: None }, &[]),
+        ContractError::Emergency {},
+    );
+
+    suite
+        .execute(ALICE, ExecuteMsg::EmergencyWithdraw { token_ids: Some(vec!["1".to_string()]) }, &[])
+        .unwrap();
+    assert_eq!(suite.nft_owner("1"), ALICE);
+    assert_eq!(suite.balance(ALICE), 10_000);
+
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::EmergencyReturnAll { limit: None }, &[]),
+        ContractError::Unauthorized {},
+    );
+    suite
+        .execute(OWNER, ExecuteMsg::EmergencyReturnAll { limit: Some(1) }, &[])
+        .unwrap();
+    suite
+        .execute(OWNER, ExecuteMsg::EmergencyReturnAll { limit: Some(1) }, &[])
+        .unwrap();
+    assert_eq!(suite.nft_owner("2"), ALICE);
+    assert_eq!(suite.nft_owner("5"), BOB);
+}
+
+#[test]
+fn emergency_return_notifies_hooks() {
+    let mut suite = Suite::new();
+    let hook_id = suite.app.store_code(hook_contract());
+    let hook = suite
+        .app
+        .instantiate_contract(hook_id, Addr::unchecked(OWNER), &Empty {}, &[], "hook", None)
+        .unwrap();
+    suite.execute(OWNER, ExecuteMsg::AddHook { addr: hook.clone() }, &[]).unwrap();
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    suite.stake(BOB, "5").unwrap();
+
+    suite.execute(OWNER, ExecuteMsg::SetEmergency { enabled: true }, &[]).unwrap();
+    suite.execute(OWNER, ExecuteMsg::EmergencyReturnAll { limit: None }, &[]).unwrap();
+
+    let events: Vec<StakeChangedHookMsg> = suite.app.wrap().query_wasm_smart(&hook, &Empty {}).unwrap();
+    let summary: Vec<_> = events[3..].iter().map(|e| (e.kind.clone(), e.user.as_str(), e.token_id.as_str(), e.count)).collect();
+    assert_eq!(
+        summary,
+        vec![
+            (StakeChangeKind::Unstake, ALICE, "1", 1),
+            (StakeChangeKind::Unstake, ALICE, "2", 0),
+            (StakeChangeKind::Unstake, BOB, "5", 0),
+        ]
+    );
+}
+
+#[test]
+fn failing_hooks_do_not_block_emergency_exits() {
+    let mut suite = Suite::new();
+    let hook_id = suite.app.store_code(failing_hook_contract());
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    suite.stake(BOB, "5").unwrap();
+    let hook = suite
+        .app
+        .instantiate_contract(hook_id, Addr::unchecked(OWNER), &Empty {}, &[], "hook", None)
+        .unwrap();
+    suite.execute(OWNER, ExecuteMsg::AddHook { addr: hook }, &[]).unwrap();
+    suite.execute(OWNER, ExecuteMsg::SetEmergency { enabled: true }, &[]).unwrap();
+
+    // Hook errors are fatal, yet both emergency exits go through.
+    let res = suite
+        .execute(ALICE, ExecuteMsg::EmergencyWithdraw { token_ids: Some(vec!["1".to_string()]) }, &[])
+        .unwrap();
+    assert!(res.events.iter().any(|e| e.ty == "wasm-aliens_staking/hook_failed"));
+    suite.execute(OWNER, ExecuteMsg::EmergencyReturnAll { limit: None }, &[]).unwrap();
+    assert_eq!(suite.nft_owner("1"), ALICE);
+    assert_eq!(suite.nft_owner("2"), ALICE);
+    assert_eq!(suite.nft_owner("5"), BOB);
+}
+
+#[test]
+fn pause_flags_are_granular() {
+    let mut suite = Suite::new();
+    suite.stake(ALICE, "1").unwrap();
+    suite.airdrop(100).unwrap();
+    suite.execute(OWNER, ExecuteMsg::AirdropRestart {}, &[]).unwrap();
+
+    suite
+        .execute(OWNER, ExecuteMsg::UpdatePauser { pauser: Some(Addr::unchecked(BOB)) }, &[])
+        .unwrap();
+    let flags = PauseFlags { stake: true, ..PauseFlags::default() };
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::UpdatePauseFlags { flags: flags.clone() }, &[]),
+        ContractError::Unauthorized {},
+    );
+    suite.execute(BOB, ExecuteMsg::UpdatePauseFlags { flags }, &[]).unwrap();
+
+    assert_error(suite.stake(ALICE, "2"), ContractError::Paused { operation: "stake".to_string() });
+    suite
+        .execute(ALICE, ExecuteMsg::Claim { claim_nft_id: "1".to_string(), recipient: None, owner: None }, &[])
+        .unwrap();
+}
+
+#[test]
+fn hooks_receive_stake_changes() {
+    let mut suite = Suite::new();
+    let hook_id = suite.app.store_code(hook_contract());
+    let hook = suite
+        .app
+        .instantiate_contract(hook_id, Addr::unchecked(OWNER), &Empty {}, &[], "hook", None)
+        .unwrap();
+
+    assert_error(
+        suite.execute(ALICE, ExecuteMsg::AddHook { addr: hook.clone() }, &[]),
+        ContractError::Unauthorized {},
+    );
+    suite.execute(OWNER, ExecuteMsg::AddHook { addr: hook.clone() }, &[]).unwrap();
+    let hooks: HooksResponse = suite.query(&QueryMsg::Hooks {});
+    assert_eq!(hooks, HooksResponse { hooks: vec![hook.to_string()], fatal: true });
+
+    suite.stake(ALICE, "1").unwrap();
+    suite.stake(ALICE, "2").unwrap();
+    suite.airdrop(1_000).unwrap();
+    suite.advance(DURATION + 1);
+    suite.execute(ALICE, ExecuteMsg::Unstake { unstake_nft_id: "1".to_string() }, &[]).unwrap();
+
+    let events: Vec<StakeChangedHookMsg> = suite.app.wrap().query_wasm_smart(&hook, &Empty {}).unwrap();
+    let summary: Vec<_> = events.iter().map(|e| (e.kind.clone(), e.token_id.as_str(), e.count)).collect();
+    assert_eq!(
+        summary,
+        vec![(StakeChangeKind::Stake, "1", 1), (StakeChangeKind::Stake, "2", 2), (StakeChangeKind::Unstake, "1", 1)]
+    );
+    assert_eq!(events[0].user, Addr::unchecked(ALICE));
+    assert_eq!(events[0].collection, suite.collection);
+
+    // A failing hook reverts the claim while hook errors are fatal.
+    let claim = ExecuteMsg::Claim { claim_nft_id: "2".to_string(), recipient: None, owner: None };
+    assert!(suite.execute(ALICE, claim.clone(), &[]).is_err());
+
+    suite.execute(OWNER, ExecuteMsg::UpdateHookErrorsFatal { fatal: false }, &[]).unwrap();
+    let res = suite.execute(ALICE, claim, &[]).unwrap();
+    assert!(res.events.iter().any(|e| e.attributes.iter().any(|a| a.key == "action" && a.value == "hook_failed")));
+
+    suite.execute(OWNER, ExecuteMsg::RemoveHook { addr: hook }, &[]).unwrap();
+    let hooks: HooksResponse = suite.query(&QueryMsg::Hooks {});
+    assert!(hooks.hooks.is_empty());
+}
+
+/// The multi-test app has no staking module, so delegation runs against
+/// the mock querier.
+mod delegation {
+    use super::*;
+    use crate::state::{NftInfo, UserInfo, ACCOUNT_MAP, DELEGATIONS, UNCLAIMED_REWARDS, VALIDATOR_DELEGATIONS};
+    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR};
+    use cosmwasm_std::{from_binary, Attribute, CosmosMsg, DistributionMsg, FullDelegation, OwnedDeps, StakingMsg, Validator};
+
+    const VALIDATOR: &str = "validator";
+
+    fn setup() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            owner: Addr::unchecked(OWNER),
+            fee_address: Addr::unchecked(FEE_ADDRESS),
+            collection_address: Addr::unchecked("collection"),
+            native_token: DENOM.to_string(),
+            duration: DURATION,
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg).unwrap();
+        deps
+    }
+
+    fn set_bonded(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, amount: u128, rewards: u128) {
+        let validator = Validator {
+            address: VALIDATOR.to_string(),
+            commission: Decimal::percent(5),
+            max_commission: Decimal::percent(10),
+            max_change_rate: Decimal::percent(1),
+        };
+        let delegation = FullDelegation {
+            delegator: Addr::unchecked(MOCK_CONTRACT_ADDR),
+            validator: VALIDATOR.to_string(),
+            amount: coin(amount, DENOM),
+            can_redelegate: coin(0, DENOM),
+            accumulated_rewards: coins(rewards, DENOM),
+        };
+        deps.querier.update_staking(DENOM, &[validator], &[delegation]);
+    }
+
+    #[test]
+    fn delegate_mode_needs_a_known_validator() {
+        let mut deps = setup();
+        set_bonded(&mut deps, 0, 0);
+        let set_mode = |validator: &str| ExecuteMsg::SetClaimMode {
+            mode: ClaimMode::Delegate { validator: validator.to_string() },
+        };
+
+        let err = execute(deps.as_mut(), mock_env(), mock_info(ALICE, &[]), set_mode("unknown")).unwrap_err();
+        assert_eq!(err, ContractError::InvalidValidator {});
+        execute(deps.as_mut(), mock_env(), mock_info(ALICE, &[]), set_mode(VALIDATOR)).unwrap();
+    }
+
+    #[test]
+    fn slashes_are_shared_by_delegators() {
+        let mut deps = setup();
+        for address in [ALICE, BOB] {
+            DELEGATIONS.save(deps.as_mut().storage, (Addr::unchecked(address), VALIDATOR.to_string()), &Uint128::new(100)).unwrap();
+        }
+        VALIDATOR_DELEGATIONS.save(deps.as_mut().storage, VALIDATOR.to_string(), &Uint128::new(200)).unwrap();
+        let undelegate = ExecuteMsg::Undelegate { validator: VALIDATOR.to_string(), amount: Uint128::new(100) };
+
+        // A quarter of the delegation was slashed away.
+        set_bonded(&mut deps, 150, 0);
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ALICE, &[]), undelegate.clone()).unwrap();
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Staking(StakingMsg::Undelegate { validator: VALIDATOR.to_string(), amount: coin(75, DENOM) })
+        );
+        assert!(res.events[0].attributes.contains(&Attribute::new("amount", "75")));
+
+        set_bonded(&mut deps, 75, 0);
+        execute(deps.as_mut(), mock_env(), mock_info(BOB, &[]), undelegate).unwrap();
+
+        // Bob's unbonding was slashed away entirely; the rewards owed to
+        // stakers are not used to cover it.
+        UNCLAIMED_REWARDS.save(deps.as_mut().storage, &Uint128::new(75)).unwrap();
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(21 * 86_400);
+        deps.querier.update_balance(MOCK_CONTRACT_ADDR, coins(150, DENOM));
+        let res = execute(deps.as_mut(), env.clone(), mock_info(ALICE, &[]), ExecuteMsg::ClaimUndelegated {}).unwrap();
+        assert!(res.events[0].attributes.contains(&Attribute::new("amount", "75")));
+        deps.querier.update_balance(MOCK_CONTRACT_ADDR, coins(75, DENOM));
+        let err = execute(deps.as_mut(), env, mock_info(BOB, &[]), ExecuteMsg::ClaimUndelegated {}).unwrap_err();
+        assert_eq!(err, ContractError::InsufficientCw20 {});
+    }
+
+    #[test]
+    fn staking_rewards_compound_for_delegators() {
+        let mut deps = setup();
+        for address in [ALICE, BOB] {
+            DELEGATIONS.save(deps.as_mut().storage, (Addr::unchecked(address), VALIDATOR.to_string()), &Uint128::new(100)).unwrap();
+        }
+        VALIDATOR_DELEGATIONS.save(deps.as_mut().storage, VALIDATOR.to_string(), &Uint128::new(200)).unwrap();
+
+        // The 40 accrued are delegated back and Alice's half of the shares
+        // unbonds half of the compounded delegation.
+        set_bonded(&mut deps, 200, 40);
+        let undelegate = ExecuteMsg::Undelegate { validator: VALIDATOR.to_string(), amount: Uint128::new(100) };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ALICE, &[]), undelegate).unwrap();
+        let msgs: Vec<_> = res.messages.into_iter().map(|msg| msg.msg).collect();
+        assert_eq!(
+            msgs,
+            vec![
+                CosmosMsg::Distribution(DistributionMsg::WithdrawDelegatorReward { validator: VALIDATOR.to_string() }),
+                CosmosMsg::Staking(StakingMsg::Delegate { validator: VALIDATOR.to_string(), amount: coin(40, DENOM) }),
+                CosmosMsg::Staking(StakingMsg::Undelegate { validator: VALIDATOR.to_string(), amount: coin(120, DENOM) }),
+            ]
+        );
+
+        // Bob's shares are worth his half of the rewards accrued since.
+        set_bonded(&mut deps, 120, 30);
+        let delegations = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>, address: &str| {
+            let res = query(deps.as_ref(), mock_env(), QueryMsg::Delegations { address: Addr::unchecked(address) }).unwrap();
+            from_binary::<DelegationsResponse>(&res).unwrap().delegations
+        };
+        let bob = &delegations(&deps, BOB)[0];
+        assert_eq!((bob.shares, bob.amount), (Uint128::new(100), Uint128::new(150)));
+
+        // A claim in delegate mode buys shares at the compounded value.
+        let nftinfo = NftInfo {
+            nft_id: "1".to_string(),
+            lock_time: 0,
+            airdrop: Uint128::new(60),
+            collection_address: Addr::unchecked("collection"),
+            auto_renew: false,
+            receipt: false,
+            staked_at: 0,
+            streak: 0,
+            duration: None,
+            locktime_fee: None,
+            multiplier: None,
+        };
+        let userinfo = UserInfo { address: Addr::unchecked(ALICE), total_earnd: Uint128::zero(), staked_nfts: vec![nftinfo] };
+        ACCOUNT_MAP.save(deps.as_mut().storage, Addr::unchecked(ALICE), &userinfo).unwrap();
+        deps.querier.update_balance(MOCK_CONTRACT_ADDR, coins(60, DENOM));
+        let set_mode = ExecuteMsg::SetClaimMode { mode: ClaimMode::Delegate { validator: VALIDATOR.to_string() } };
+        execute(deps.as_mut(), mock_env(), mock_info(ALICE, &[]), set_mode).unwrap();
+        let claim = ExecuteMsg::Claim { claim_nft_id: "1".to_string(), recipient: None, owner: None };
+        let res = execute(deps.as_mut(), mock_env(), mock_info(ALICE, &[]), claim).unwrap();
+        assert_eq!(
+            res.messages[1].msg,
+            CosmosMsg::Staking(StakingMsg::Delegate { validator: VALIDATOR.to_string(), amount: coin(30, DENOM) })
+        );
+
+        set_bonded(&mut deps, 210, 0);
+        let alice = &delegations(&deps, ALICE)[0];
+        assert_eq!((alice.shares, alice.amount), (Uint128::new(40), Uint128::new(60)));
+        let bob = &delegations(&deps, BOB)[0];
+        assert_eq!((bob.shares, bob.amount), (Uint128::new(100), Uint128::new(150)));
+    }
+}
+
+/// Random sequences of user and owner actions, checked against the
+/// accounting invariants after every step.
+mod invariants {
+    use super::*;
+    use crate::state::{
+        UserInfo, ACCOUNT_MAP, AIRDROP_DUST_KEY, TOTAL_AIRDROP_KEY, TOTAL_STAKED_KEY, UNCLAIMED_REWARDS_KEY,
+    };
+    use cosmwasm_std::from_slice;
+    use proptest::prelude::*;
+
+    const TOKENS: [&str; 6] = ["1", "2", "3", "4", "5", "6"];
+
+    #[derive(Clone, Debug)]
+    enum Action {
+        Stake(usize),
+        Unstake(usize, bool),
+        Restake(usize),
+        Claim(usize),
+        Airdrop(u128),
+        Sponsor(u128),
+        AirdropRestart,
+        Advance(u64),
+    }
+
+    fn action() -> impl Strategy<Value = Action> {
+        prop_oneof![
+            3 => (0..TOKENS.len()).prop_map(Action::Stake),
+            2 => (0..TOKENS.len(), any::<bool>()).prop_map(|(token, pay_fee)| Action::Unstake(token, pay_fee)),
+            1 => (0..TOKENS.len()).prop_map(Action::Restake),
+            2 => (0..TOKENS.len()).prop_map(Action::Claim),
+            2 => (1..1_000u128).prop_map(Action::Airdrop),
+            1 => (1..1_000u128).prop_map(Action::Sponsor),
+            1 => Just(Action::AirdropRestart),
+            2 => (0..2 * DURATION).prop_map(Action::Advance),
+        ]
+    }
+
+    fn token_owner(token: &str) -> &'static str {
+        if token < "5" { ALICE } else { BOB }
+    }
+
+    /// Totals the harness keeps on its own, independent of contract state.
+    #[derive(Default)]
+    struct Ledger {
+        funded: u128,
+        distributed: u128,
+        forfeited: u128,
+    }
+
+    impl Suite {
+        fn user_info(&self, address: &str) -> Option<UserInfo> {
+            let key = ACCOUNT_MAP.key(Addr::unchecked(address));
+            self.app
+                .wrap()
+                .query_wasm_raw(self.staking.clone(), key.to_vec())
+                .unwrap()
+                .map(|raw| from_slice(&raw).unwrap())
+        }
+
+        fn raw_item<T: serde::de::DeserializeOwned>(&self, key: &str) -> T {
+            let raw = self
+                .app
+                .wrap()
+                .query_wasm_raw(self.staking.clone(), key.as_bytes())
+                .unwrap()
+                .unwrap();
+            from_slice(&raw).unwrap()
+        }
+
+        fn pending(&self) -> u128 {
+            [ALICE, BOB]
+                .iter()
+                .filter_map(|address| self.user_info(address))
+                .flat_map(|userinfo| userinfo.staked_nfts)
+                .map(|nftinfo| nftinfo.airdrop.u128())
+                .sum()
+        }
+
+        fn earned(&self) -> u128 {
+            [ALICE, BOB]
+                .iter()
+                .filter_map(|address| self.user_info(address))
+                .map(|userinfo| userinfo.total_earnd.u128())
+                .sum()
+        }
+
+        fn nft_airdrop(&self, token: &str) -> Option<u128> {
+            self.user_info(token_owner(token))?
+                .staked_nfts
+                .iter()
+                .find(|nftinfo| nftinfo.nft_id == token)
+                .map(|nftinfo| nftinfo.airdrop.u128())
+        }
+    }
+
+    fn apply(suite: &mut Suite, ledger: &mut Ledger, action: &Action) {
+        match action {
+            Action::Stake(token) => {
+                let token = TOKENS[*token];
+                let _ = suite.stake(token_owner(token), token);
+            }
+            Action::Unstake(token, pay_fee) => {
+                let token = TOKENS[*token];
+                let airdrop = suite.nft_airdrop(token);
+                let funds = if *pay_fee { coins(LOCKTIME_FEE, DENOM) } else { vec![] };
+                let msg = ExecuteMsg::Unstake { unstake_nft_id: token.to_string() };
+                if suite.execute(token_owner(token), msg, &funds).is_ok() {
+                    ledger.forfeited += airdrop.unwrap();
+                }
+            }
+            Action::Restake(token) => {
+                let token = TOKENS[*token];
+                let msg = ExecuteMsg::Restake { restake_nft_id: token.to_string(), owner: None };
+                let _ = suite.execute(token_owner(token), msg, &[]);
+            }
+            Action::Claim(token) => {
+                let token = TOKENS[*token];
+                let msg = ExecuteMsg::Claim { claim_nft_id: token.to_string(), recipient: None, owner: None };
+                let _ = suite.execute(token_owner(token), msg, &[]);
+            }
+            Action::Airdrop(amount) => {
+                let before = suite.pending();
+                let dust: Uint128 = suite.raw_item(AIRDROP_DUST_KEY);
+                ledger.funded += amount;
+                if suite.airdrop(*amount).is_ok() {
+                    let credited = suite.pending() - before;
+                    assert!(credited <= amount + dust.u128());
+                    assert!(amount + dust.u128() - credited < TOKENS.len() as u128);
+                    ledger.distributed += amount;
+                }
+            }
+            Action::Sponsor(amount) => {
+                let msg = ExecuteMsg::FundAirdrop {};
+                if suite.execute(OWNER, msg, &coins(*amount, DENOM)).is_ok() {
+                    ledger.funded += amount;
+                    ledger.distributed += amount;
+                }
+            }
+            Action::AirdropRestart => {
+                suite.execute(OWNER, ExecuteMsg::AirdropRestart {}, &[]).unwrap();
+            }
+            Action::Advance(seconds) => suite.advance(*seconds),
+        }
+    }
+
+    fn check(suite: &Suite, ledger: &Ledger) {
+        let pending = suite.pending();
+        let earned = suite.earned();
+
+        // Every token that went in is either paid out, owed, forfeited on
+        // unstake or carried forward as rounding dust.
+        let dust: Uint128 = suite.raw_item(AIRDROP_DUST_KEY);
+        let total_airdrop: Uint128 = suite.raw_item(TOTAL_AIRDROP_KEY);
+        assert_eq!(suite.balance(suite.staking.as_str()), ledger.funded - earned);
+        assert_eq!(pending + earned + ledger.forfeited + dust.u128(), ledger.distributed);
+        assert_eq!(total_airdrop.u128(), pending + earned + ledger.forfeited);
+        let unclaimed: Uint128 = suite.raw_item(UNCLAIMED_REWARDS_KEY);
+        assert_eq!(unclaimed.u128(), pending);
+
+        // Each token is held by its owner or staked exactly once.
+        let mut staked: Vec<String> = [ALICE, BOB]
+            .iter()
+            .filter_map(|address| suite.user_info(address))
+            .flat_map(|userinfo| userinfo.staked_nfts)
+            .map(|nftinfo| nftinfo.nft_id)
+            .collect();
+        staked.sort();
+        let count = staked.len();
+        staked.dedup();
+        assert_eq!(staked.len(), count);
+        for token in TOKENS {
+            let owner = suite.nft_owner(token);
+            if staked.iter().any(|nft_id| nft_id == token) {
+                assert_eq!(owner, suite.staking.to_string());
+            } else {
+                assert_eq!(owner, token_owner(token));
+            }
+        }
+
+        let total_staked: u64 = suite.raw_item(TOTAL_STAKED_KEY);
+        assert_eq!(total_staked as usize, count);
+
+        // The voting power total tracks the per-account snapshots.
+        let total_power: TotalPowerAtHeightResponse = suite.query(&QueryMsg::TotalPowerAtHeight { height: None });
+        let account_power: Uint128 = [ALICE, BOB]
+            .iter()
+            .map(|address| {
+                let res: VotingPowerAtHeightResponse =
+                    suite.query(&QueryMsg::VotingPowerAtHeight { address: address.to_string(), height: None });
+                res.power
+            })
+            .sum();
+        assert_eq!(total_power.power, account_power);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn accounting_invariants_hold(actions in prop::collection::vec(action(), 1..40)) {
+            let mut suite = Suite::new();
+            let mut ledger = Ledger::default();
+            for action in actions.iter() {
+                apply(&mut suite, &mut ledger, action);
+                check(&suite, &ledger);
             }
-        }))?
-        .deposit(parse_gas!("5950000000000000000000"))
-        .transact()
-        .await?;
-
-    // root approves alice
-    owner
-        .call(&worker, nft_contract.id(), "nft_approve")
-        .args_json(json!({
-            "token_id":  "0",
-            "account_id": user.id(),
-        }))?
-        .deposit(parse_gas!("5950000000000000000000"))
-        .transact()
-        .await?;
-
-    let approval_no_id: bool = nft_contract
-        .call(&worker, "nft_is_approved")
-        .args_json(json!({
-            "token_id":  "0",
-            "approved_account_id": user.id()
-        }))?
-        .transact()
-        .await?
-        .json()?;
-
-    assert!(approval_no_id);
-
-    let approval: bool = nft_contract
-        .call(&worker, "nft_is_approved")
-        .args_json(json!({
-            "token_id":  "0",
-            "approved_account_id": user.id(),
-            "approval_id": 1
-        }))?
-        .transact()
-        .await?
-        .json()?;
-
-    assert!(approval);
-
-    let approval_wrong_id: bool = nft_contract
-        .call(&worker, "nft_is_approved")
-        .args_json(json!({
-            "token_id":  "0",
-            "approved_account_id": user.id(),
-            "approval_id": 2
-        }))?
-        .transact()
-        .await?
-        .json()?;
-
-    assert!(!approval_wrong_id);
-    println!("      Passed ✅ test_simple_approve");
-    Ok(())
+        }
+    }
 }
@@ -1,28 +1,132 @@
-use std::convert::{From, TryFrom};
+use std::convert::From;
 use cosmwasm_std::{
-    to_binary,  Response, StdResult, Uint128, Coin, BankMsg,
-    WasmMsg, WasmQuery, QueryRequest, Addr, Storage, CosmosMsg,  QuerierWrapper, BalanceResponse as NativeBalanceResponse, BankQuery, Order, BlockInfo
+    to_binary, coin, Response, SubMsg, StdResult, Uint128, Coin, BankMsg, DistributionMsg, StakingMsg,
+    WasmMsg, WasmQuery, QueryRequest, Addr, Api, Empty, Storage, CosmosMsg,  QuerierWrapper, BalanceResponse as NativeBalanceResponse, BankQuery, Order, BlockInfo, Decimal, Event
 };
 use cw20::{Cw20ExecuteMsg, Denom, BalanceResponse as CW20BalanceResponse, Cw20QueryMsg};
+use cw721::{Cw721QueryMsg, OwnerOfResponse};
 use crate::error::ContractError;
+use crate::msg::{StakeChangeKind, StakeChangedExecuteMsg, StakeChangedHookMsg};
 use crate::state::{
     CONFIG,
     ACCOUNT_MAP, 
     LOCKTIME_FEE, 
     START_AIRDROP,
+    TOTAL_AIRDROP,
+    UNCLAIMED_REWARDS,
+    TOTAL_STAKED,
+    CURRENT_AIRDROP,
+    STREAK_TIERS,
+    TIMELOCK_DELAY,
+    NEXT_CHANGE_ID,
+    PENDING_CHANGES,
+    RENEWAL_WINDOW,
+    AIRDROP_DUST,
+    LOCK_TIERS,
+    MAX_LOCK_DURATION,
+    UNBONDING_PERIOD,
+    EMERGENCY,
+    PAUSE_FLAGS,
+    PAUSER,
+    OPERATORS,
+    VAULT,
+    UNDELEGATION_PERIOD,
+    HOOKS,
+    HOOK_ERRORS_FATAL,
+    VOTING_POWER,
+    TOTAL_POWER,
+    STAKING_AGENTS,
+    RECEIPT_COLLECTION,
+    RECEIPT_HOLDERS,
+    RECEIPT_IDS,
+    RECEIPT_TOKENS,
+    VOIDED_RECEIPTS,
+    DENIED_TOKENS,
+    DENIED_ADDRESSES,
+    DENYLIST_MODE,
+    Config,
+    DenylistMode,
+    Weighting,
+    StreakTier,
+    ConfigChange,
+    PendingChange,
+    PauseFlags,
+    LockTier,
+    NftInfo,
     UserInfo,
 };
 
+pub const HOOK_REPLY_ID: u64 = 1;
+pub const RECEIPT_REPLY_ID: u64 = 2;
+
+/// Prefix of every event type this contract emits.
+pub const EVENT_PREFIX: &str = "aliens_staking";
+
+/// Starts the `aliens_staking/<action>` event of a handler. Event types and
+/// attribute keys are listed in the README and must stay stable.
+pub fn event(action: &str) -> Event {
+    Event::new(format!("{}/{}", EVENT_PREFIX, action))
+}
+
+/// Event for `action` on one NFT of `user`, carrying its stored lock terms.
+pub fn nft_event(action: &str, user: &Addr, nftinfo: &NftInfo) -> Event {
+    event(action)
+        .add_attribute("user", user)
+        .add_attribute("collection", &nftinfo.collection_address)
+        .add_attribute("token_id", nftinfo.nft_id.clone())
+        .add_attribute("lock_time", nftinfo.lock_time.to_string())
+        .add_attribute("auto_renew", nftinfo.auto_renew.to_string())
+        .add_attribute("receipt", nftinfo.receipt.to_string())
+        .add_attributes(nftinfo.duration.map(|duration| ("duration", duration.to_string())))
+        .add_attributes(nftinfo.locktime_fee.map(|fee| ("locktime_fee", fee)))
+}
+
+/// Voting power of one NFT in an untiered lock.
+pub const VOTING_POWER_PER_NFT: u128 = 1_000_000;
+/// Longest unbonding period the owner can set, one year.
+pub const MAX_UNBONDING_PERIOD: u64 = 365 * 86_400;
+
+/// Operations that can be paused on their own through `PauseFlags`.
+pub enum Operation {
+    Stake,
+    Unstake,
+    Claim,
+    Restake,
+    Airdrop,
+}
+
 pub fn check_enabled(
     storage: &mut dyn Storage,
+    operation: Operation,
 ) -> Result<Response, ContractError> {
     let cfg = CONFIG.load(storage)?;
     if !cfg.enabled {
         return Err(ContractError::Disabled {  })
     }
+
+    let flags = PAUSE_FLAGS.load(storage)?;
+    let (paused, name) = match operation {
+        Operation::Stake => (flags.stake, "stake"),
+        Operation::Unstake => (flags.unstake, "unstake"),
+        Operation::Claim => (flags.claim, "claim"),
+        Operation::Restake => (flags.restake, "restake"),
+        Operation::Airdrop => (flags.airdrop, "airdrop"),
+    };
+    if paused {
+        return Err(ContractError::Paused { operation: name.to_string() })
+    }
     Ok(Response::new().add_attribute("action", "check_enabled"))
 }
 
+pub fn check_not_emergency(
+    storage: &mut dyn Storage,
+) -> Result<Response, ContractError> {
+    if EMERGENCY.load(storage)? {
+        return Err(ContractError::Emergency {  })
+    }
+    Ok(Response::new().add_attribute("action", "check_not_emergency"))
+}
+
 pub fn check_airdrop_start(
     storage: &mut dyn Storage,
 ) -> Result<Response, ContractError> {
@@ -47,6 +151,25 @@ pub fn check_owner(
     )
 }
 
+/// Returns the staker `sender` acts for. Acting for another `owner` needs an
+/// unexpired `ApproveAll` from that owner.
+pub fn resolve_staker(
+    storage: &dyn Storage,
+    block: &BlockInfo,
+    sender: Addr,
+    owner: Option<Addr>,
+) -> Result<Addr, ContractError> {
+    match owner {
+        Some(owner) if owner != sender => {
+            match OPERATORS.may_load(storage, (owner.clone(), sender))? {
+                Some(expires) if !expires.is_expired(block) => Ok(owner),
+                _ => Err(ContractError::Unauthorized {  }),
+            }
+        },
+        _ => Ok(sender),
+    }
+}
+
 pub fn execute_update_owner(
     storage: &mut dyn Storage,
     address: Addr,
@@ -60,8 +183,10 @@ pub fn execute_update_owner(
     })?;
 
     Ok(Response::new()
-        .add_attribute("action", "update_config")
-        .add_attribute("owner", owner.clone())
+        .add_attribute("action", "update_owner")
+        .add_event(event("update_owner")
+            .add_attribute("owner", owner)
+        )
     )
 }
 
@@ -78,8 +203,204 @@ pub fn execute_update_fee_address(
     })?;
 
     Ok(Response::new()
-        .add_attribute("action", "update_config")
-        .add_attribute("fee_address", fee_address.clone())
+        .add_attribute("action", "update_fee_address")
+        .add_event(event("update_fee_address")
+            .add_attribute("fee_address", fee_address)
+        )
+    )
+}
+
+/// Queues `change` to become executable once the timelock delay has passed.
+/// It is validated now, so a change that could never apply is refused
+/// up front.
+pub fn execute_queue_change(
+    storage: &mut dyn Storage,
+    address: Addr,
+    block: &BlockInfo,
+    change: ConfigChange
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+
+    let change = match change {
+        ConfigChange::Config {
+            new_owner,
+            new_fee_address,
+            new_collection_address,
+            new_duration,
+            new_locktime_fee
+        } => {
+            // Keep only what differs from the current config.
+            let cfg = CONFIG.load(storage)?;
+            let locktime_fee = LOCKTIME_FEE.load(storage)?;
+            ConfigChange::Config {
+                new_owner: new_owner.filter(|owner| *owner != cfg.owner),
+                new_fee_address: new_fee_address.filter(|fee_address| *fee_address != cfg.fee_address),
+                new_collection_address: new_collection_address.filter(|collection| *collection != cfg.collection_address),
+                new_duration: new_duration.filter(|duration| *duration != cfg.duration),
+                new_locktime_fee: new_locktime_fee.filter(|fee| *fee != locktime_fee),
+            }
+        },
+        ConfigChange::UnbondingPeriod { unbonding_period } => {
+            check_unbonding_period(unbonding_period)?;
+            ConfigChange::UnbondingPeriod { unbonding_period }
+        },
+        ConfigChange::LockTiers { tiers, max_lock_duration } => {
+            check_lock_tiers(&tiers, max_lock_duration)?;
+            ConfigChange::LockTiers { tiers, max_lock_duration }
+        },
+        ConfigChange::StakingLimits { max_per_user, max_total_staked, weighting } => {
+            check_weighting(&weighting)?;
+            ConfigChange::StakingLimits { max_per_user, max_total_staked, weighting }
+        },
+        change => change,
+    };
+
+    let id = NEXT_CHANGE_ID.load(storage)?;
+    NEXT_CHANGE_ID.save(storage, &(id + 1))?;
+    let eta = block.time.seconds() + TIMELOCK_DELAY.load(storage)?;
+    PENDING_CHANGES.save(storage, id, &PendingChange { change: change.clone(), eta })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "queue_change")
+        .add_event(event("queue_change")
+            .add_attribute("id", id.to_string())
+            .add_attribute("change", change_kind(&change))
+            .add_attribute("eta", eta.to_string())
+        )
+    )
+}
+
+pub fn execute_apply_change(
+    storage: &mut dyn Storage,
+    address: Addr,
+    block: &BlockInfo,
+    id: u64
+) -> Result<Response, ContractError> {
+    check_owner(storage, address.clone())?;
+
+    let pending = PENDING_CHANGES.may_load(storage, id)?.ok_or(ContractError::NoPendingChange {  })?;
+    if block.time.seconds() < pending.eta {
+        return Err(ContractError::TimelockNotExpired { eta: pending.eta });
+    }
+    PENDING_CHANGES.remove(storage, id);
+
+    let kind = change_kind(&pending.change);
+    let response = match pending.change {
+        ConfigChange::Owner { owner } => execute_update_owner(storage, address, owner)?,
+        ConfigChange::FeeAddress { fee_address } => execute_update_fee_address(storage, address, fee_address)?,
+        ConfigChange::Duration { duration } => execute_update_duration(storage, address, duration)?,
+        ConfigChange::Config {
+            new_owner,
+            new_fee_address,
+            new_collection_address,
+            new_duration,
+            new_locktime_fee
+        } => {
+            let collection_changed = new_collection_address.is_some();
+            let response = execute_update_config(storage, address, new_owner, new_fee_address, new_collection_address, new_duration, new_locktime_fee)?;
+            // Voting power only counts NFTs of the staking collection.
+            if collection_changed {
+                sync_all_voting_power(storage, block)?;
+            }
+            response
+        },
+        ConfigChange::TimelockDelay { delay } => {
+            TIMELOCK_DELAY.save(storage, &delay)?;
+            Response::new()
+                .add_attribute("action", "update_timelock_delay")
+                .add_event(event("update_timelock_delay")
+                    .add_attribute("delay", delay.to_string())
+                )
+        },
+        ConfigChange::UnbondingPeriod { unbonding_period } => execute_update_unbonding_period(storage, address, unbonding_period)?,
+        ConfigChange::LockTiers { tiers, max_lock_duration } => execute_update_lock_tiers(storage, address, tiers, max_lock_duration)?,
+        ConfigChange::StakingLimits {
+            max_per_user,
+            max_total_staked,
+            weighting
+        } => execute_update_staking_limits(storage, address, max_per_user, max_total_staked, weighting)?,
+        ConfigChange::Vault { vault } => execute_update_vault(storage, address, vault)?,
+    };
+
+    Ok(response
+        .add_event(event("execute_change")
+            .add_attribute("id", id.to_string())
+            .add_attribute("change", kind)
+        )
+    )
+}
+
+pub fn execute_cancel_change(
+    storage: &mut dyn Storage,
+    address: Addr,
+    id: u64
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+
+    let pending = PENDING_CHANGES.may_load(storage, id)?.ok_or(ContractError::NoPendingChange {  })?;
+    PENDING_CHANGES.remove(storage, id);
+
+    Ok(Response::new()
+        .add_attribute("action", "cancel_change")
+        .add_event(event("cancel_change")
+            .add_attribute("id", id.to_string())
+            .add_attribute("change", change_kind(&pending.change))
+        )
+    )
+}
+
+fn change_kind(change: &ConfigChange) -> &'static str {
+    match change {
+        ConfigChange::Owner { .. } => "owner",
+        ConfigChange::FeeAddress { .. } => "fee_address",
+        ConfigChange::Duration { .. } => "duration",
+        ConfigChange::Config { .. } => "config",
+        ConfigChange::TimelockDelay { .. } => "timelock_delay",
+        ConfigChange::UnbondingPeriod { .. } => "unbonding_period",
+        ConfigChange::LockTiers { .. } => "lock_tiers",
+        ConfigChange::StakingLimits { .. } => "staking_limits",
+        ConfigChange::Vault { .. } => "vault",
+    }
+}
+
+pub fn execute_update_pauser(
+    storage: &mut dyn Storage,
+    address: Addr,
+    pauser: Option<Addr>
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+
+    PAUSER.save(storage, &pauser)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_pauser")
+        .add_event(event("update_pauser")
+            .add_attributes(pauser.map(|pauser| ("pauser", pauser)))
+        )
+    )
+}
+
+pub fn execute_update_pause_flags(
+    storage: &mut dyn Storage,
+    address: Addr,
+    flags: PauseFlags
+) -> Result<Response, ContractError> {
+    let pauser = PAUSER.load(storage)?;
+    if pauser != Some(address.clone()) {
+        check_owner(storage, address)?;
+    }
+
+    PAUSE_FLAGS.save(storage, &flags)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_pause_flags")
+        .add_event(event("update_pause_flags")
+            .add_attribute("stake", flags.stake.to_string())
+            .add_attribute("unstake", flags.unstake.to_string())
+            .add_attribute("claim", flags.claim.to_string())
+            .add_attribute("restake", flags.restake.to_string())
+            .add_attribute("airdrop", flags.airdrop.to_string())
+        )
     )
 }
 
@@ -97,33 +418,175 @@ pub fn execute_update_enabled (
 
     Ok(Response::new()
         .add_attribute("action", "update_enabled")
+        .add_event(event("update_enabled")
+            .add_attribute("enabled", enabled.to_string())
+        )
     )
 }
 
+pub fn execute_set_emergency(
+    storage: &mut dyn Storage,
+    address: Addr,
+    enabled: bool
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+
+    EMERGENCY.save(storage, &enabled)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_emergency")
+        .add_event(event("set_emergency")
+            .add_attribute("emergency", enabled.to_string())
+        )
+    )
+}
+
+/// Sets the fields that are `Some` and keeps the others.
 pub fn execute_update_config(
     storage: &mut dyn Storage,
     address: Addr,
-    new_owner: Addr,
-    new_fee_address: Addr,
-    new_collection_address: Addr,
-    new_duration: u64,
-    new_locktime_fee: Uint128
+    new_owner: Option<Addr>,
+    new_fee_address: Option<Addr>,
+    new_collection_address: Option<Addr>,
+    new_duration: Option<u64>,
+    new_locktime_fee: Option<Uint128>
 ) -> Result<Response, ContractError> {
     check_owner(storage, address)?;
     
-    CONFIG.update(storage, |mut exists| -> StdResult<_> {
-        exists.owner = new_owner;
-        exists.fee_address = new_fee_address;
-        exists.collection_address = new_collection_address;
-        exists.duration = new_duration;
+    let cfg = CONFIG.update(storage, |mut exists| -> StdResult<_> {
+        exists.owner = new_owner.unwrap_or(exists.owner);
+        exists.fee_address = new_fee_address.unwrap_or(exists.fee_address);
+        exists.collection_address = new_collection_address.unwrap_or(exists.collection_address);
+        exists.duration = new_duration.unwrap_or(exists.duration);
         Ok(exists)
     })?;
 
-    LOCKTIME_FEE.update(storage, |_| -> StdResult<_> {
-        Ok(new_locktime_fee)
+    let new_locktime_fee = LOCKTIME_FEE.update(storage, |exists| -> StdResult<_> {
+        Ok(new_locktime_fee.unwrap_or(exists))
     })?;
     
-    Ok(Response::new().add_attribute("action", "update_config"))
+    Ok(Response::new()
+        .add_attribute("action", "update_config")
+        .add_event(event("update_config")
+            .add_attribute("owner", cfg.owner)
+            .add_attribute("fee_address", cfg.fee_address)
+            .add_attribute("collection", cfg.collection_address)
+            .add_attribute("duration", cfg.duration.to_string())
+            .add_attribute("locktime_fee", new_locktime_fee)
+        )
+    )
+}
+
+pub fn execute_update_staking_limits(
+    storage: &mut dyn Storage,
+    address: Addr,
+    max_per_user: Option<u64>,
+    max_total_staked: Option<u64>,
+    weighting: Weighting
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+    check_weighting(&weighting)?;
+
+    let cfg = CONFIG.update(storage, |mut exists| -> StdResult<_> {
+        exists.max_per_user = max_per_user;
+        exists.max_total_staked = max_total_staked;
+        exists.weighting = weighting;
+        Ok(exists)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_staking_limits")
+        .add_event(event("update_staking_limits")
+            .add_attributes(cfg.max_per_user.map(|max| ("max_per_user", max.to_string())))
+            .add_attributes(cfg.max_total_staked.map(|max| ("max_total_staked", max.to_string())))
+            .add_attributes(match cfg.weighting {
+                Weighting::Linear {  } => vec![("weighting", "linear".to_string())],
+                Weighting::Diminishing { threshold, factor } => vec![
+                    ("weighting", "diminishing".to_string()),
+                    ("threshold", threshold.to_string()),
+                    ("factor", factor.to_string()),
+                ],
+            })
+        )
+    )
+}
+
+fn check_weighting(weighting: &Weighting) -> Result<(), ContractError> {
+    if let Weighting::Diminishing { factor, .. } = weighting {
+        if *factor > Decimal::one() {
+            return Err(ContractError::InvalidWeighting {  });
+        }
+    }
+    Ok(())
+}
+
+pub fn execute_update_airdrop_eligibility(
+    storage: &mut dyn Storage,
+    address: Addr,
+    min_stake_age: u64,
+    time_weighted: bool
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+
+    CONFIG.update(storage, |mut exists| -> StdResult<_> {
+        exists.min_stake_age = min_stake_age;
+        exists.time_weighted = time_weighted;
+        Ok(exists)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_airdrop_eligibility")
+        .add_event(event("update_airdrop_eligibility")
+            .add_attribute("min_stake_age", min_stake_age.to_string())
+            .add_attribute("time_weighted", time_weighted.to_string())
+        )
+    )
+}
+
+/// Whether `nftinfo` has been staked for at least `min_stake_age`.
+pub fn is_old_enough(cfg: &Config, nftinfo: &NftInfo, now: u64) -> bool {
+    now.saturating_sub(nftinfo.staked_at) >= cfg.min_stake_age
+}
+
+/// Scales `weight` by the part of the epoch from `epoch_start` to `now`
+/// that `nftinfo` spent staked.
+pub fn get_time_weight(weight: Uint128, nftinfo: &NftInfo, epoch_start: u64, now: u64) -> Uint128 {
+    let epoch = now.saturating_sub(epoch_start);
+    if epoch == 0 {
+        return weight;
+    }
+    let staked = now.saturating_sub(nftinfo.staked_at.max(epoch_start));
+    weight.multiply_ratio(staked, epoch)
+}
+
+/// Fails when staking one more NFT for an account that already has
+/// `user_count` staked would break a cap.
+pub fn check_staking_limits(
+    storage: &dyn Storage,
+    cfg: &Config,
+    user_count: usize,
+) -> Result<(), ContractError> {
+    if let Some(max_per_user) = cfg.max_per_user {
+        if user_count as u64 >= max_per_user {
+            return Err(ContractError::MaxPerUserExceeded { max_per_user });
+        }
+    }
+    if let Some(max_total_staked) = cfg.max_total_staked {
+        if TOTAL_STAKED.load(storage)? >= max_total_staked {
+            return Err(ContractError::MaxTotalStakedExceeded { max_total_staked });
+        }
+    }
+    Ok(())
+}
+
+/// Applies `weighting` to the airdrop weights of one account's NFTs, in
+/// staking order.
+pub fn apply_weighting(weighting: &Weighting, weights: &mut [Uint128]) {
+    if let Weighting::Diminishing { threshold, factor } = weighting {
+        for weight in weights.iter_mut().filter(|weight| !weight.is_zero()).skip(*threshold as usize) {
+            *weight = *weight * *factor;
+        }
+    }
 }
 
 pub fn execute_update_duration(
@@ -138,7 +601,435 @@ pub fn execute_update_duration(
         Ok(exists)
     })?;
 
-    Ok(Response::new().add_attribute("action", "update_config"))
+    Ok(Response::new()
+        .add_attribute("action", "update_duration")
+        .add_event(event("update_duration")
+            .add_attribute("duration", duration.to_string())
+        )
+    )
+}
+
+pub fn execute_update_lock_tiers(
+    storage: &mut dyn Storage,
+    address: Addr,
+    mut tiers: Vec<LockTier>,
+    max_lock_duration: u64
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+    check_lock_tiers(&tiers, max_lock_duration)?;
+    tiers.sort_by_key(|tier| tier.duration);
+
+    LOCK_TIERS.save(storage, &tiers)?;
+    MAX_LOCK_DURATION.save(storage, &max_lock_duration)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_lock_tiers")
+        .add_event(event("update_lock_tiers")
+            .add_attribute("tiers", tiers.iter()
+                .map(|tier| format!("{}:{}", tier.duration, tier.multiplier))
+                .collect::<Vec<String>>()
+                .join(","))
+            .add_attribute("max_lock_duration", max_lock_duration.to_string())
+        )
+    )
+}
+
+fn check_lock_tiers(tiers: &[LockTier], max_lock_duration: u64) -> Result<(), ContractError> {
+    if tiers.iter().any(|tier| tier.multiplier.is_zero() || tier.duration > max_lock_duration) {
+        return Err(ContractError::InvalidTier {  });
+    }
+    Ok(())
+}
+
+pub fn execute_update_streak_tiers(
+    storage: &mut dyn Storage,
+    address: Addr,
+    mut tiers: Vec<StreakTier>,
+    renewal_window: u64
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+
+    if tiers.iter().any(|tier| tier.streak == 0 || tier.multiplier.is_zero()) {
+        return Err(ContractError::InvalidStreakTier {  });
+    }
+    tiers.sort_by_key(|tier| tier.streak);
+
+    STREAK_TIERS.save(storage, &tiers)?;
+    RENEWAL_WINDOW.save(storage, &renewal_window)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_streak_tiers")
+        .add_event(event("update_streak_tiers")
+            .add_attributes((!tiers.is_empty()).then(|| ("tiers", tiers.iter()
+                .map(|tier| format!("{}:{}", tier.streak, tier.multiplier))
+                .collect::<Vec<String>>()
+                .join(","))))
+            .add_attribute("renewal_window", renewal_window.to_string())
+        )
+    )
+}
+
+pub fn execute_update_unbonding_period(
+    storage: &mut dyn Storage,
+    address: Addr,
+    unbonding_period: u64
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+    check_unbonding_period(unbonding_period)?;
+
+    UNBONDING_PERIOD.save(storage, &unbonding_period)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_unbonding_period")
+        .add_event(event("update_unbonding_period")
+            .add_attribute("unbonding_period", unbonding_period.to_string())
+        )
+    )
+}
+
+fn check_unbonding_period(unbonding_period: u64) -> Result<(), ContractError> {
+    if unbonding_period > MAX_UNBONDING_PERIOD {
+        return Err(ContractError::InvalidUnbondingPeriod { max_unbonding_period: MAX_UNBONDING_PERIOD });
+    }
+    Ok(())
+}
+
+pub fn execute_update_vault(
+    storage: &mut dyn Storage,
+    address: Addr,
+    vault: Option<Addr>
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+
+    VAULT.save(storage, &vault)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_vault")
+        .add_event(event("update_vault")
+            .add_attributes(vault.map(|vault| ("vault", vault)))
+        )
+    )
+}
+
+pub fn execute_update_undelegation_period(
+    storage: &mut dyn Storage,
+    address: Addr,
+    undelegation_period: u64
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+
+    UNDELEGATION_PERIOD.save(storage, &undelegation_period)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_undelegation_period")
+        .add_event(event("update_undelegation_period")
+            .add_attribute("undelegation_period", undelegation_period.to_string())
+        )
+    )
+}
+
+pub fn execute_update_hook_errors_fatal(
+    storage: &mut dyn Storage,
+    address: Addr,
+    fatal: bool
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+
+    HOOK_ERRORS_FATAL.save(storage, &fatal)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_hook_errors_fatal")
+        .add_event(event("update_hook_errors_fatal")
+            .add_attribute("fatal", fatal.to_string())
+        )
+    )
+}
+
+pub fn execute_update_staking_agents(
+    api: &dyn Api,
+    storage: &mut dyn Storage,
+    address: Addr,
+    add: Vec<Addr>,
+    remove: Vec<Addr>
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+
+    for agent in add.iter() {
+        let agent = api.addr_validate(agent.as_str())?;
+        STAKING_AGENTS.save(storage, agent, &Empty {})?;
+    }
+    for agent in remove.iter() {
+        STAKING_AGENTS.remove(storage, agent.clone());
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "update_staking_agents")
+        .add_event(event("update_staking_agents")
+            .add_attributes(add.into_iter().map(|agent| ("add", agent)))
+            .add_attributes(remove.into_iter().map(|agent| ("remove", agent)))
+        )
+    )
+}
+
+pub fn execute_update_denylist(
+    api: &dyn Api,
+    storage: &mut dyn Storage,
+    address: Addr,
+    add_tokens: Vec<String>,
+    remove_tokens: Vec<String>,
+    add_addresses: Vec<Addr>,
+    remove_addresses: Vec<Addr>
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+
+    for token_id in add_tokens.iter() {
+        DENIED_TOKENS.save(storage, token_id.clone(), &Empty {})?;
+    }
+    for token_id in remove_tokens.iter() {
+        DENIED_TOKENS.remove(storage, token_id.clone());
+    }
+    for denied in add_addresses.iter() {
+        let denied = api.addr_validate(denied.as_str())?;
+        DENIED_ADDRESSES.save(storage, denied, &Empty {})?;
+    }
+    for denied in remove_addresses.iter() {
+        DENIED_ADDRESSES.remove(storage, denied.clone());
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "update_denylist")
+        .add_event(event("update_denylist")
+            .add_attributes(add_tokens.into_iter().map(|token_id| ("add_token", token_id)))
+            .add_attributes(remove_tokens.into_iter().map(|token_id| ("remove_token", token_id)))
+            .add_attributes(add_addresses.into_iter().map(|denied| ("add_address", denied)))
+            .add_attributes(remove_addresses.into_iter().map(|denied| ("remove_address", denied)))
+        )
+    )
+}
+
+pub fn execute_update_denylist_mode(
+    storage: &mut dyn Storage,
+    address: Addr,
+    mode: DenylistMode
+) -> Result<Response, ContractError> {
+    check_owner(storage, address)?;
+
+    DENYLIST_MODE.save(storage, &mode)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_denylist_mode")
+        .add_event(event("update_denylist_mode")
+            .add_attribute("mode", match mode {
+                DenylistMode::Reject {  } => "reject",
+                DenylistMode::Exclude {  } => "exclude",
+            })
+        )
+    )
+}
+
+/// Fails when `staker` or, in `Reject` mode, `token_id` is denylisted.
+pub fn check_not_denied(
+    storage: &dyn Storage,
+    staker: &Addr,
+    token_id: &str,
+) -> Result<(), ContractError> {
+    if DENIED_ADDRESSES.has(storage, staker.clone()) {
+        return Err(ContractError::DeniedAddress {  });
+    }
+    if DENIED_TOKENS.has(storage, token_id.to_string()) && DENYLIST_MODE.load(storage)? == (DenylistMode::Reject {  }) {
+        return Err(ContractError::DeniedToken {  });
+    }
+    Ok(())
+}
+
+/// Whether the position of `nftinfo` held by `address` is left out of
+/// airdrops because the token or the holder is denylisted.
+pub fn is_reward_excluded(
+    storage: &dyn Storage,
+    address: &Addr,
+    nftinfo: &NftInfo,
+) -> bool {
+    DENIED_TOKENS.has(storage, nftinfo.nft_id.clone()) || DENIED_ADDRESSES.has(storage, address.clone())
+}
+
+/// Returns the current holder of the receipt for `token_id`.
+pub fn get_receipt_owner(
+    storage: &dyn Storage,
+    querier: QuerierWrapper,
+    token_id: &str,
+) -> Result<Addr, ContractError> {
+    let collection = RECEIPT_COLLECTION.load(storage)?.ok_or(ContractError::NoReceiptCollection {  })?;
+    let res: OwnerOfResponse = querier.query_wasm_smart(collection, &Cw721QueryMsg::OwnerOf {
+        token_id: receipt_id(storage, token_id)?,
+        include_expired: None,
+    })?;
+    Ok(Addr::unchecked(res.owner))
+}
+
+/// Returns the receipt token id backing the position of `token_id`.
+pub fn receipt_id(storage: &dyn Storage, token_id: &str) -> StdResult<String> {
+    Ok(RECEIPT_IDS.may_load(storage, token_id.to_string())?.unwrap_or_else(|| token_id.to_string()))
+}
+
+/// Records and returns the id of a new receipt for `token_id`. It is the
+/// token id itself unless earlier receipts were voided, which still hold
+/// their ids in the collection.
+pub fn new_receipt_id(storage: &mut dyn Storage, token_id: &str) -> StdResult<String> {
+    let receipt_id = match VOIDED_RECEIPTS.may_load(storage, token_id.to_string())? {
+        Some(voided) => format!("{}.{}", token_id, voided),
+        None => token_id.to_string(),
+    };
+    RECEIPT_IDS.save(storage, token_id.to_string(), &receipt_id)?;
+    RECEIPT_TOKENS.save(storage, receipt_id.clone(), &token_id.to_string())?;
+    Ok(receipt_id)
+}
+
+/// Drops the receipt bookkeeping of `token_id`. With `void` set the receipt
+/// was not burned: it stays in the collection but redeems nothing.
+pub fn clear_receipt(storage: &mut dyn Storage, token_id: &str, void: bool) -> StdResult<()> {
+    if let Some(receipt_id) = RECEIPT_IDS.may_load(storage, token_id.to_string())? {
+        RECEIPT_TOKENS.remove(storage, receipt_id);
+    }
+    RECEIPT_IDS.remove(storage, token_id.to_string());
+    RECEIPT_HOLDERS.remove(storage, token_id.to_string());
+    if void {
+        VOIDED_RECEIPTS.update(storage, token_id.to_string(), |voided| -> StdResult<_> {
+            Ok(voided.unwrap_or_default() + 1)
+        })?;
+    }
+    Ok(())
+}
+
+/// Moves a receipt-backed position, with its pending reward and lock, to the
+/// account that holds the receipt now. Does nothing for positions without a
+/// receipt or that are already in place.
+pub fn sync_receipt(
+    storage: &mut dyn Storage,
+    querier: QuerierWrapper,
+    block: &BlockInfo,
+    token_id: &str,
+) -> Result<(Vec<Event>, Vec<SubMsg>), ContractError> {
+    let holder = match RECEIPT_HOLDERS.may_load(storage, token_id.to_string())? {
+        Some(holder) => holder,
+        None => return Ok((vec![], vec![])),
+    };
+    let owner = get_receipt_owner(storage, querier, token_id)?;
+    if owner == holder {
+        return Ok((vec![], vec![]));
+    }
+
+    RECEIPT_HOLDERS.save(storage, token_id.to_string(), &owner)?;
+    let (event, hook_msgs) = move_position(storage, block, &holder, &owner, token_id)?;
+    Ok((vec![event], hook_msgs))
+}
+
+/// Fails when `to` may not take over the staked `token_id`: it is
+/// denylisted or already holds `max_per_user` NFTs. Checked on transfers and
+/// receipt redemptions; a lazy receipt sync cannot refuse the new holder. The total cap does not
+/// apply, since a move leaves the number of staked NFTs unchanged.
+pub fn check_can_receive(
+    storage: &dyn Storage,
+    to: &Addr,
+    token_id: &str,
+) -> Result<(), ContractError> {
+    check_not_denied(storage, to, token_id)?;
+    if let Some(max_per_user) = CONFIG.load(storage)?.max_per_user {
+        let user_count = ACCOUNT_MAP.may_load(storage, to.clone())?.map_or(0, |userinfo| userinfo.staked_nfts.len());
+        if user_count as u64 >= max_per_user {
+            return Err(ContractError::MaxPerUserExceeded { max_per_user });
+        }
+    }
+    Ok(())
+}
+
+/// Moves the position of `token_id`, with its pending reward and lock, from
+/// the `UserInfo` of `from` to that of `to`. Hooks see an `Unstake` by
+/// `from` followed by a `Stake` by `to`.
+pub fn move_position(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+    from: &Addr,
+    to: &Addr,
+    token_id: &str,
+) -> Result<(Event, Vec<SubMsg>), ContractError> {
+    let mut from_info = ACCOUNT_MAP.load(storage, from.clone())?;
+    let index = from_info.staked_nfts.iter()
+        .position(|nft| nft.nft_id == token_id)
+        .ok_or(ContractError::NoStakedNft {  })?;
+    let nftinfo = from_info.staked_nfts.remove(index);
+    ACCOUNT_MAP.save(storage, from.clone(), &from_info)?;
+
+    let mut to_info = ACCOUNT_MAP.may_load(storage, to.clone())?.unwrap_or(UserInfo {
+        address: to.clone(),
+        total_earnd: Uint128::zero(),
+        staked_nfts: vec![],
+    });
+    to_info.staked_nfts.push(nftinfo.clone());
+    ACCOUNT_MAP.save(storage, to.clone(), &to_info)?;
+
+    sync_voting_power(storage, block, from)?;
+    sync_voting_power(storage, block, to)?;
+
+    let mut hook_msgs = stake_hook_msgs(storage, StakeChangeKind::Unstake, from, &nftinfo, from_info.staked_nfts.len())?;
+    hook_msgs.extend(stake_hook_msgs(storage, StakeChangeKind::Stake, to, &nftinfo, to_info.staked_nfts.len())?);
+
+    Ok((nft_event("transfer_position", to, &nftinfo)
+        .add_attribute("from", from),
+        hook_msgs,
+    ))
+}
+
+/// Builds one `StakeChangeHook` sub-message per registered hook. `count` is
+/// the number of NFTs `user` has staked after the change.
+pub fn stake_hook_msgs(
+    storage: &dyn Storage,
+    kind: StakeChangeKind,
+    user: &Addr,
+    nftinfo: &NftInfo,
+    count: usize,
+) -> StdResult<Vec<SubMsg>> {
+    let fatal = HOOK_ERRORS_FATAL.load(storage)?;
+    build_hook_msgs(storage, kind, user, nftinfo, count, fatal)
+}
+
+/// Builds the `Unstake` hooks of a position leaving in an emergency. Their
+/// errors are never fatal, so a broken hook cannot keep NFTs locked in.
+pub fn emergency_hook_msgs(
+    storage: &dyn Storage,
+    user: &Addr,
+    nftinfo: &NftInfo,
+    count: usize,
+) -> StdResult<Vec<SubMsg>> {
+    build_hook_msgs(storage, StakeChangeKind::Unstake, user, nftinfo, count, false)
+}
+
+fn build_hook_msgs(
+    storage: &dyn Storage,
+    kind: StakeChangeKind,
+    user: &Addr,
+    nftinfo: &NftInfo,
+    count: usize,
+    fatal: bool,
+) -> StdResult<Vec<SubMsg>> {
+    let msg = to_binary(&StakeChangedExecuteMsg::StakeChangeHook(StakeChangedHookMsg {
+        kind,
+        user: user.clone(),
+        collection: nftinfo.collection_address.clone(),
+        token_id: nftinfo.nft_id.clone(),
+        count: count as u64,
+    }))?;
+    HOOKS.prepare_hooks(storage, |hook| {
+        let execute = WasmMsg::Execute {
+            contract_addr: hook.to_string(),
+            msg: msg.clone(),
+            funds: vec![],
+        };
+        Ok(if fatal {
+            SubMsg::new(execute)
+        } else {
+            SubMsg::reply_on_error(execute, HOOK_REPLY_ID)
+        })
+    })
 }
 
 pub fn transfer_token_message(
@@ -149,23 +1040,23 @@ pub fn transfer_token_message(
 
     match denom.clone() {
         Denom::Native(native_str) => {
-            return Ok(BankMsg::Send {
+            Ok(BankMsg::Send {
                 to_address: receiver.clone().into(),
                 amount: vec![Coin{
                     denom: native_str,
                     amount
                 }]
-            }.into());
+            }.into())
         },
         Denom::Cw20(native_token) => {
-            return Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+            Ok(CosmosMsg::Wasm(WasmMsg::Execute {
                 contract_addr: native_token.clone().into(),
                 funds: vec![],
                 msg: to_binary(&Cw20ExecuteMsg::Transfer {
                     recipient: receiver.clone().into(),
                     amount
                 })?,
-            }));
+            }))
         }
     }
 }
@@ -182,42 +1073,341 @@ pub fn get_token_amount(
                 address: contract_addr.clone().into(),
                 denom: native_str
             }))?;
-            return Ok(native_response.amount.amount);
+            Ok(native_response.amount.amount)
         },
         Denom::Cw20(native_token) => {
             let balance_response: CW20BalanceResponse = querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
                 contract_addr: native_token.clone().into(),
                 msg: to_binary(&Cw20QueryMsg::Balance {address: contract_addr.clone().into()})?,
             }))?;
-            return Ok(balance_response.balance);
+            Ok(balance_response.balance)
         }
     }
 }
 
+/// Returns what the contract's delegation to `validator` holds in `denom`:
+/// the bonded amount and the staking rewards accrued on it since they were
+/// last withdrawn.
+pub fn query_delegation_value(
+    querier: QuerierWrapper,
+    contract_addr: &Addr,
+    validator: &str,
+    denom: &str,
+) -> StdResult<(Uint128, Uint128)> {
+    Ok(match querier.query_delegation(contract_addr, validator)? {
+        Some(delegation) => (
+            delegation.amount.amount,
+            delegation.accumulated_rewards
+                .iter()
+                .filter(|reward| reward.denom == denom)
+                .map(|reward| reward.amount)
+                .sum(),
+        ),
+        None => (Uint128::zero(), Uint128::zero()),
+    })
+}
+
+/// Shares of a validator's delegation that `amount` buys when `total_shares`
+/// are worth `value`. The first delegation gets one share per token.
+pub fn get_delegation_shares(
+    amount: Uint128,
+    total_shares: Uint128,
+    value: Uint128,
+) -> Result<Uint128, ContractError> {
+    if total_shares.is_zero() {
+        return Ok(amount);
+    }
+    if value.is_zero() {
+        return Err(ContractError::InvalidValidator {  });
+    }
+    Ok(amount.multiply_ratio(total_shares, value))
+}
+
+/// Withdraws the `rewards` accrued on the contract's delegation to
+/// `validator` and delegates them back, so they compound for every holder
+/// of its shares instead of landing in the contract's balance.
+pub fn compound_msgs(
+    validator: &str,
+    rewards: Uint128,
+    denom: &str,
+) -> Vec<CosmosMsg> {
+    if rewards.is_zero() {
+        return vec![];
+    }
+    vec![
+        DistributionMsg::WithdrawDelegatorReward { validator: validator.to_string() }.into(),
+        StakingMsg::Delegate { validator: validator.to_string(), amount: coin(rewards.u128(), denom) }.into(),
+    ]
+}
+
+/// Returns the lock time of `nftinfo` as seen at `now`. An expired lock with
+/// `auto_renew` set is rolled forward by whole periods of its recorded
+/// duration, or of `duration` when none was recorded, so it always ends
+/// strictly after `now`.
+pub fn get_lock_time(
+    nftinfo: &NftInfo,
+    duration: u64,
+    now: u64,
+) -> u64 {
+    let duration = nftinfo.duration.unwrap_or(duration);
+    if !nftinfo.auto_renew || duration == 0 || nftinfo.lock_time > now {
+        return nftinfo.lock_time;
+    }
+    let periods = (now - nftinfo.lock_time) / duration + 1;
+    nftinfo.lock_time + periods * duration
+}
+
+/// Returns the reward multiplier of the longest tier whose duration fits in
+/// `remaining` seconds of lock. Without tiers every lock weighs one.
+pub fn get_lock_multiplier(
+    tiers: &[LockTier],
+    remaining: u64,
+) -> Decimal {
+    tiers.iter()
+        .rev()
+        .find(|tier| tier.duration <= remaining)
+        .map(|tier| tier.multiplier)
+        .unwrap_or_else(Decimal::one)
+}
+
+/// Returns the streak of `nftinfo` at `now`. Periods rolled by auto-renew
+/// count as renewals; a fixed lock that ended more than `renewal_window`
+/// seconds ago has lost its streak.
+pub fn get_streak(
+    nftinfo: &NftInfo,
+    duration: u64,
+    renewal_window: u64,
+    now: u64,
+) -> u32 {
+    let duration = nftinfo.duration.unwrap_or(duration);
+    if nftinfo.lock_time > now {
+        return nftinfo.streak;
+    }
+    if nftinfo.auto_renew && duration > 0 {
+        let periods = (now - nftinfo.lock_time) / duration + 1;
+        return nftinfo.streak.saturating_add(periods.min(u32::MAX as u64) as u32);
+    }
+    if now - nftinfo.lock_time > renewal_window {
+        return 0;
+    }
+    nftinfo.streak
+}
+
+/// Returns the account streak `stored`, or zero once any of `staked_nfts`
+/// has let its renewal window pass.
+pub fn get_user_streak(
+    stored: u32,
+    staked_nfts: &[NftInfo],
+    renewal_window: u64,
+    now: u64,
+) -> u32 {
+    let missed = staked_nfts.iter()
+        .any(|nftinfo| !nftinfo.auto_renew && now > nftinfo.lock_time.saturating_add(renewal_window));
+    if missed { 0 } else { stored }
+}
+
+/// Returns the multiplier of the highest streak tier `streak` has reached.
+pub fn get_streak_multiplier(
+    tiers: &[StreakTier],
+    streak: u32,
+) -> Decimal {
+    tiers.iter()
+        .rev()
+        .find(|tier| tier.streak <= streak)
+        .map(|tier| tier.multiplier)
+        .unwrap_or_else(Decimal::one)
+}
+
+/// Returns the airdrop weight of `nftinfo` at `now`, or zero when it is not
+/// eligible for a share. A lock weighs the tier multiplier fixed when it was
+/// set, so one untiered lock weighs `Decimal::one().atomics()`.
+pub fn get_airdrop_weight(
+    nftinfo: &NftInfo,
+    cfg: &Config,
+    now: u64,
+) -> Uint128 {
+    let lock_time = get_lock_time(nftinfo, cfg.duration, now);
+    if lock_time <= now || nftinfo.collection_address != cfg.collection_address {
+        return Uint128::zero();
+    }
+    nftinfo.multiplier.unwrap_or_else(Decimal::one).atomics()
+}
+
+/// Voting power of `userinfo` at `now`: every locked NFT of the staking
+/// collection counts `VOTING_POWER_PER_NFT` scaled by the tier multiplier
+/// fixed when its lock was set, as its airdrop weight does.
+pub fn get_voting_power(
+    userinfo: &UserInfo,
+    cfg: &Config,
+    now: u64,
+) -> Uint128 {
+    let weight: Uint128 = userinfo.staked_nfts
+        .iter()
+        .map(|nftinfo| get_airdrop_weight(nftinfo, cfg, now))
+        .sum();
+    weight.multiply_ratio(VOTING_POWER_PER_NFT, Decimal::one().atomics())
+}
+
+/// Snapshots the voting power of `address` and the total at the current
+/// height. Called whenever the account's stakes or locks change, and by
+/// `SyncVotingPower` to drop the power of locks that ran out since.
+pub fn sync_voting_power(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+    address: &Addr,
+) -> StdResult<()> {
+    let cfg = CONFIG.load(storage)?;
+    let power = match ACCOUNT_MAP.may_load(storage, address.clone())? {
+        Some(userinfo) => get_voting_power(&userinfo, &cfg, block.time.seconds()),
+        None => Uint128::zero(),
+    };
+    let previous = VOTING_POWER.may_load(storage, address.clone())?.unwrap_or_default();
+    if power == previous {
+        return Ok(());
+    }
+
+    VOTING_POWER.save(storage, address.clone(), &power, block.height)?;
+    let total = TOTAL_POWER.may_load(storage)?.unwrap_or_default() + power - previous;
+    TOTAL_POWER.save(storage, &total, block.height)
+}
+
+/// Resyncs the voting power of every account, after a config change that
+/// decides which positions count.
+pub fn sync_all_voting_power(
+    storage: &mut dyn Storage,
+    block: &BlockInfo,
+) -> StdResult<()> {
+    let addresses: Vec<Addr> = ACCOUNT_MAP.keys(storage, None, None, Order::Ascending).collect::<StdResult<_>>()?;
+    for address in addresses.iter() {
+        sync_voting_power(storage, block, address)?;
+    }
+    Ok(())
+}
+
 pub fn get_in_locktime_nft_count(
     storage: &dyn Storage,
     block: BlockInfo,
     collection_address: Addr,
 ) -> Result<Uint128, ContractError> {
     let mut count = 0;
+    let cfg = CONFIG.load(storage)?;
+    let duration = cfg.duration;
+    let all_accounts: Vec<(Addr, UserInfo)> = ACCOUNT_MAP.range(storage, None, None, Order::Ascending).collect::<StdResult<_>>()?;
+
+    for (address, userinfo) in all_accounts.iter() {
+        count += userinfo.staked_nfts
+            .iter()
+            .filter(|nftinfo| 
+                get_lock_time(nftinfo, duration, block.time.seconds()) > block.time.seconds() && nftinfo.collection_address == collection_address 
+                    && !is_reward_excluded(storage, address, nftinfo)
+                    && is_old_enough(&cfg, nftinfo, block.time.seconds())
+            ).count();
+    }
+
+    Ok(Uint128::from(count as u128))
+}
+
+/// Credits `amount`, plus the dust carried from earlier rounds, to every
+/// eligible NFT in proportion to its airdrop weight. Returns the share of one
+/// untiered NFT, the amount credited, the dust carried forward, the total
+/// weight the amount was split over and an `airdrop_credit` event per NFT
+/// credited.
+pub fn distribute_airdrop(
+    storage: &mut dyn Storage,
+    block: BlockInfo,
+    amount: Uint128,
+) -> Result<(Uint128, Uint128, Uint128, Uint128, Vec<Event>), ContractError> {
+    let cfg = CONFIG.load(storage)?;
+    let nft_count = get_in_locktime_nft_count(storage, block.clone(), cfg.collection_address.clone())?;
+
+    if nft_count.is_zero() {
+        return Err(ContractError::NoUnexpiredNft {  });
+    }
+
+    let distribute_amount = amount + AIRDROP_DUST.load(storage)?;
+
+    if distribute_amount < nft_count {
+        return Err(ContractError::OverNftCount { 
+            nft_count 
+        });
+    }
+
+    let now = block.time.seconds();
+
     let result: StdResult<Vec<(Addr, UserInfo)>> = ACCOUNT_MAP.range(storage, None, None, Order::Ascending).collect();
-    
-    match result {
-        Ok(all_accounts) => {
-            for (_address, userinfo) in all_accounts.iter() {
-                count += userinfo.staked_nfts
-                    .iter()
-                    .filter(|nftinfo| 
-                        (nftinfo.lock_time > block.time.seconds() && nftinfo.collection_address == collection_address)
-                    ).count();
+    let all_accounts = match result {
+        Ok(all_accounts) => all_accounts,
+        Err(_error) => return Err(ContractError::NoAirdropNft {  }),
+    };
+
+    let epoch_start = CURRENT_AIRDROP.load(storage)?.time.seconds();
+    let streak_tiers = STREAK_TIERS.load(storage)?;
+    let renewal_window = RENEWAL_WINDOW.load(storage)?;
+    let weight_of = |address: &Addr, nftinfo: &NftInfo| {
+        if is_reward_excluded(storage, address, nftinfo) || !is_old_enough(&cfg, nftinfo, now) {
+            return Uint128::zero();
+        }
+        let mut weight = get_airdrop_weight(nftinfo, &cfg, now);
+        if cfg.time_weighted {
+            weight = get_time_weight(weight, nftinfo, epoch_start, now);
+        }
+        weight * get_streak_multiplier(&streak_tiers, get_streak(nftinfo, cfg.duration, renewal_window, now))
+    };
+    let mut weights = vec![];
+    for (address, userinfo) in all_accounts.iter() {
+        let mut user_weights = userinfo.staked_nfts.iter().map(|nftinfo| weight_of(address, nftinfo)).collect::<Vec<_>>();
+        // Receipts synced to an account bypass `max_per_user`; positions
+        // past the cap earn nothing.
+        if let Some(max_per_user) = cfg.max_per_user {
+            user_weights.iter_mut().skip(max_per_user as usize).for_each(|weight| *weight = Uint128::zero());
+        }
+        apply_weighting(&cfg.weighting, &mut user_weights);
+        weights.push(user_weights);
+    }
+    let total_weight: Uint128 = weights.iter().flatten().sum();
+    if total_weight.is_zero() {
+        return Err(ContractError::NoUnexpiredNft {  });
+    }
+
+    let mut credited = Uint128::zero();
+    let mut events = vec![];
+    for ((address, mut userinfo), weights) in all_accounts.into_iter().zip(weights) {
+        for (nftinfo, weight) in userinfo.staked_nfts.iter_mut().zip(weights) {
+            if !weight.is_zero() {
+                let share = distribute_amount.multiply_ratio(weight, total_weight);
+                nftinfo.airdrop += share;
+                credited += share;
+                events.push(nft_event("airdrop_credit", &address, nftinfo)
+                    .add_attribute("amount", share)
+                );
             }
-    
-            return Ok(Uint128::from(u128::try_from(count).unwrap()));
-        },
-        Err(_error) => {
-            return Err(crate::ContractError::NoStakedNft {  });
         }
+        ACCOUNT_MAP.save(storage, address, &userinfo)?;
     }
+
+    let remainder = distribute_amount - credited;
+    TOTAL_AIRDROP.update(storage, |exists| -> StdResult<_> {
+        Ok(exists + credited)
+    })?;
+    UNCLAIMED_REWARDS.update(storage, |exists| -> StdResult<_> {
+        Ok(exists + credited)
+    })?;
+    AIRDROP_DUST.save(storage, &remainder)?;
+
+    Ok((distribute_amount.multiply_ratio(Decimal::one().atomics(), total_weight), credited, remainder, total_weight, events))
+}
+
+/// Native tokens the contract owes: unclaimed rewards plus the dust the
+/// next airdrop will hand out. Neither may be withdrawn or airdropped again.
+pub fn get_reserved_rewards(storage: &dyn Storage) -> StdResult<Uint128> {
+    Ok(UNCLAIMED_REWARDS.load(storage)? + AIRDROP_DUST.load(storage)?)
+}
+
+/// Releases `amount` of unclaimed rewards that were paid out or forfeited.
+pub fn release_rewards(storage: &mut dyn Storage, amount: Uint128) -> StdResult<Uint128> {
+    UNCLAIMED_REWARDS.update(storage, |exists| -> StdResult<_> {
+        Ok(exists.saturating_sub(amount))
+    })
 }
 
 pub fn set_airdrop(
@@ -234,10 +1424,10 @@ pub fn set_airdrop(
             nftinfo.airdrop += airdrop;
             userinfo.staked_nfts[index] = nftinfo;
             ACCOUNT_MAP.save(storage, address.clone(), &userinfo)?;
-            return Ok(Response::default());
+            Ok(Response::default())
         },
         None => {
-            return Err(crate::ContractError::NoStakedNft {  });
+            Err(crate::ContractError::NoStakedNft {  })
         }
-    };
+    }
 }